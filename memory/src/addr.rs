@@ -0,0 +1,357 @@
+//! Physical and virtual memory addresses. They're separate types so that one can't be used where
+//! the other is expected, E.G. a virtual address can't be stored in a page table entry.
+
+use core::{fmt, ops};
+
+/// A physical memory address, which is at most [`PhysAddr::BITS`] bits.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PhysAddr(usize);
+
+/// A canonical virtual memory address, I.E. bits 48-63 are copies of bit 47.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct VirtAddr(usize);
+
+impl PhysAddr {
+    /// The number of bits a physical address can have.
+    pub const BITS: u32 = 52;
+
+    /// Returns `addr` as a physical address, or `None` if it has bits above [`Self::BITS`] set.
+    pub const fn try_new(addr: usize) -> Option<Self> {
+        if addr >> Self::BITS == 0 {
+            Some(Self(addr))
+        } else {
+            None
+        }
+    }
+}
+
+impl VirtAddr {
+    /// Returns `addr` as a virtual address, or `None` if it isn't canonical.
+    pub const fn try_new(addr: usize) -> Option<Self> {
+        // Bits 47-63 must either all be clear, or all be set
+        match addr >> 47 {
+            0 | 0x1_ffff => Some(Self(addr)),
+            _ => None,
+        }
+    }
+
+    /// Returns the address of `ptr`.
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self::new(ptr as usize)
+    }
+
+    /// Cast this address to a const pointer of the specified type.
+    /// # Safety
+    /// Use of this pointer requires that it is a valid pointer to something of the correct type.
+    pub unsafe fn as_ptr<T>(self) -> *const T {
+        self.0 as _
+    }
+
+    /// Cast this address to a mutable pointer of the specified type.
+    /// # Safety
+    /// Use of this pointer requires that it is a valid pointer to something of the correct type.
+    pub unsafe fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as _
+    }
+
+    /// Index of the P4 entry used to translate this address.
+    pub const fn p4_index(self) -> usize {
+        (self.0 >> 39) & 0o777
+    }
+
+    /// Index of the P3 entry used to translate this address.
+    pub const fn p3_index(self) -> usize {
+        (self.0 >> 30) & 0o777
+    }
+
+    /// Index of the P2 entry used to translate this address.
+    pub const fn p2_index(self) -> usize {
+        (self.0 >> 21) & 0o777
+    }
+
+    /// Index of the P1 entry used to translate this address.
+    pub const fn p1_index(self) -> usize {
+        (self.0 >> 12) & 0o777
+    }
+
+    /// Offset of this address into its 4K page.
+    pub const fn page_offset(self) -> usize {
+        self.0 & 0xfff
+    }
+}
+
+/// Implement the operations shared by [`PhysAddr`] and [`VirtAddr`], in terms of the type's
+/// `try_new`, which decides which addresses are valid.
+macro_rules! impl_addr {
+    ($name:ident, $display:literal) => {
+        impl $name {
+            pub const NULL: Self = Self(0);
+
+            /// Returns `addr` as an address of this type.
+            ///
+            /// # Panics
+            ///
+            #[doc = concat!("* If `addr` isn't a valid ", $display, ".")]
+            pub const fn new(addr: usize) -> Self {
+                match Self::try_new(addr) {
+                    Some(addr) => addr,
+                    None => panic!(concat!("invalid ", $display)),
+                }
+            }
+
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+
+            /// Returns whether this address is considered to be `NULL`. Note that, even if this
+            /// function returns `true`, it may still be possible to use this address, depending on
+            /// how the page tables or physical frames are set up.
+            pub fn is_null(self) -> bool {
+                self == Self::NULL
+            }
+
+            /// Aligns the address to `alignment` (E.G. 4096) downwards.
+            #[inline]
+            pub fn align_down(self, alignment: usize) -> Self {
+                // Clearing low bits can't make an address invalid
+                Self(self.0 & !(alignment - 1))
+            }
+
+            /// Aligns the address to `alignment` (E.G. 4096) upwards.
+            ///
+            /// # Panics
+            ///
+            /// * If the aligned address isn't valid.
+            #[inline]
+            pub fn align_up(self, alignment: usize) -> Self {
+                Self::new((self.0 + alignment - 1) & !(alignment - 1))
+            }
+
+            /// Returns `self + rhs`, or `None` if that isn't a valid address.
+            pub fn checked_add(self, rhs: usize) -> Option<Self> {
+                Self::try_new(self.0.checked_add(rhs)?)
+            }
+
+            /// Returns `self - rhs`, or `None` if that isn't a valid address.
+            pub fn checked_sub(self, rhs: usize) -> Option<Self> {
+                Self::try_new(self.0.checked_sub(rhs)?)
+            }
+        }
+
+        impl From<$name> for usize {
+            fn from(addr: $name) -> Self {
+                addr.0
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(addr: $name) -> Self {
+                addr.0 as _
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#16x}", self.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(self, f)
+            }
+        }
+
+        impl ops::Add<usize> for $name {
+            type Output = Self;
+
+            fn add(self, rhs: usize) -> Self::Output {
+                self.checked_add(rhs)
+                    .expect(concat!("address overflow: not a valid ", $display))
+            }
+        }
+
+        impl ops::AddAssign<usize> for $name {
+            fn add_assign(&mut self, rhs: usize) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl ops::Sub<usize> for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: usize) -> Self::Output {
+                self.checked_sub(rhs)
+                    .expect(concat!("address underflow: not a valid ", $display))
+            }
+        }
+
+        impl ops::Sub for $name {
+            type Output = usize;
+
+            fn sub(self, rhs: Self) -> Self::Output {
+                self.0 - rhs.0
+            }
+        }
+    };
+}
+
+impl_addr!(PhysAddr, "physical address");
+impl_addr!(VirtAddr, "virtual address");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+
+    #[test]
+    fn align_up() {
+        assert_eq!(PhysAddr(0).align_up(4096), PhysAddr(0));
+        assert_eq!(PhysAddr(1).align_up(4096), PhysAddr(4096));
+        assert_eq!(PhysAddr(4096).align_up(4096), PhysAddr(4096));
+        assert_eq!(PhysAddr(4097).align_up(4096), PhysAddr(8192));
+        assert_eq!(PhysAddr(0x1234).align_up(0x20_0000), PhysAddr(0x20_0000));
+    }
+
+    #[test]
+    fn align_down() {
+        assert_eq!(PhysAddr(0).align_down(4096), PhysAddr(0));
+        assert_eq!(PhysAddr(4095).align_down(4096), PhysAddr(0));
+        assert_eq!(PhysAddr(4097).align_down(4096), PhysAddr(4096));
+    }
+
+    #[test]
+    #[should_panic]
+    fn align_up_into_hole() {
+        VirtAddr(0x0000_7fff_ffff_f001).align_up(4096);
+    }
+
+    #[test]
+    fn align_random() {
+        let mut rng = Rng::new();
+        for _ in 0..10_000 {
+            let alignment = 1 << rng.range(0..40);
+            // Leave room to align up without leaving the lower half
+            let addr = VirtAddr(rng.range(0..1 << 46) as usize);
+
+            let down = addr.align_down(alignment);
+            assert!(down <= addr && addr - down < alignment);
+            assert!(down.0.is_multiple_of(alignment));
+
+            let up = addr.align_up(alignment);
+            assert!(up >= addr && up - addr < alignment);
+            assert!(up.0.is_multiple_of(alignment));
+
+            assert_eq!(up == down, addr.0.is_multiple_of(alignment));
+        }
+    }
+
+    #[test]
+    fn virt_addr_is_canonical() {
+        assert!(VirtAddr::try_new(0).is_some());
+        assert!(VirtAddr::try_new(0x0000_7fff_ffff_ffff).is_some());
+        assert!(VirtAddr::try_new(0x0000_8000_0000_0000).is_none());
+        assert!(VirtAddr::try_new(0xffff_7fff_ffff_ffff).is_none());
+        assert!(VirtAddr::try_new(0xffff_8000_0000_0000).is_some());
+        assert!(VirtAddr::try_new(usize::MAX).is_some());
+    }
+
+    #[test]
+    fn virt_addr_is_canonical_random() {
+        let mut rng = Rng::new();
+        let sign_extend = |addr: u64| ((addr << 16) as i64 >> 16) as u64;
+        for i in 0..10_000 {
+            let mut addr = rng.next_u64();
+            // Most random addresses aren't canonical, so make half of them canonical
+            if i % 2 == 0 {
+                addr = sign_extend(addr);
+            }
+            // Canonical addresses are unchanged by sign extending bit 47
+            assert_eq!(
+                VirtAddr::try_new(addr as usize).is_some(),
+                addr == sign_extend(addr)
+            );
+        }
+    }
+
+    #[test]
+    fn phys_addr_is_52_bits() {
+        assert!(PhysAddr::try_new(0).is_some());
+        assert!(PhysAddr::try_new(0x000f_ffff_ffff_ffff).is_some());
+        assert!(PhysAddr::try_new(0x0010_0000_0000_0000).is_none());
+        assert!(PhysAddr::try_new(usize::MAX).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn phys_addr_new_panics() {
+        PhysAddr::new(1 << 52);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let top = PhysAddr(0x000f_ffff_ffff_f000);
+        assert_eq!(
+            top.checked_add(0xfff),
+            Some(PhysAddr(0x000f_ffff_ffff_ffff))
+        );
+        assert_eq!(top.checked_add(0x1000), None);
+        assert_eq!(PhysAddr(0x1000).checked_sub(0x1000), Some(PhysAddr(0)));
+        assert_eq!(PhysAddr(0x1000).checked_sub(0x1001), None);
+
+        // Virtual addresses can't be moved into, or across, the non-canonical hole
+        let lower_end = VirtAddr(0x0000_7fff_ffff_f000);
+        let higher_start = VirtAddr(0xffff_8000_0000_0000);
+        assert_eq!(lower_end.checked_add(0x1000), None);
+        assert_eq!(
+            lower_end.checked_add(higher_start.0 - lower_end.0),
+            Some(higher_start)
+        );
+        assert_eq!(higher_start.checked_sub(1), None);
+        assert_eq!(VirtAddr(usize::MAX).checked_add(1), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn add_panics_on_overflow() {
+        let _ = VirtAddr(usize::MAX) + 1;
+    }
+
+    #[test]
+    fn table_indices() {
+        let addr = VirtAddr::new(0xffff_ff80_4020_1abc);
+        assert_eq!(addr.p4_index(), 511);
+        assert_eq!(addr.p3_index(), 1);
+        assert_eq!(addr.p2_index(), 1);
+        assert_eq!(addr.p1_index(), 1);
+        assert_eq!(addr.page_offset(), 0xabc);
+    }
+
+    #[test]
+    fn table_indices_random() {
+        let mut rng = Rng::new();
+        for _ in 0..10_000 {
+            let indices = [0; 4].map(|_| rng.range(0..512) as usize);
+            let offset = rng.range(0..4096) as usize;
+            let mut addr = indices.iter().fold(0, |addr, &index| addr << 9 | index) << 12 | offset;
+            if indices[0] >= 256 {
+                // Sign extend into the higher half
+                addr |= 0xffff_0000_0000_0000;
+            }
+
+            let addr = VirtAddr::new(addr);
+            assert_eq!(
+                [
+                    addr.p4_index(),
+                    addr.p3_index(),
+                    addr.p2_index(),
+                    addr.p1_index()
+                ],
+                indices
+            );
+            assert_eq!(addr.page_offset(), offset);
+        }
+    }
+}
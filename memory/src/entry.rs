@@ -0,0 +1,215 @@
+//! Page table entries.
+
+use core::marker::PhantomData;
+
+use crate::{
+    frame::{AllocatedFrame, SizedAllocatedFrame},
+    level::{Level1, Level2, Level3, Level4, PageTableLevel},
+    PhysAddr, Size1G, Size2M, Size4K,
+};
+
+/// Bits of an entry holding the physical address it points to. The address is always page
+/// aligned, and physical addresses are at most 52 bits.
+const ADDRESS_MASK: u64 = 0x000fffff_fffff000;
+
+/// An entry in a page table
+#[derive(Clone, Copy)]
+pub struct Entry<L: PageTableLevel> {
+    inner: u64,
+    _marker: PhantomData<L>,
+}
+
+impl<L: PageTableLevel> Default for Entry<L> {
+    fn default() -> Self {
+        Self {
+            inner: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<L: PageTableLevel> Entry<L> {
+    pub fn is_unused(&self) -> bool {
+        self.inner == 0
+    }
+
+    pub fn set_unused(&mut self) {
+        self.inner = 0;
+    }
+
+    pub fn flags(&self) -> EntryFlags {
+        EntryFlags::from_bits_truncate(self.inner)
+    }
+
+    pub fn set_flags(&mut self, flags: EntryFlags) {
+        self.inner = (self.inner & ADDRESS_MASK) | flags.bits();
+    }
+
+    pub fn addr(&self) -> PhysAddr {
+        PhysAddr::new((self.inner & ADDRESS_MASK) as usize)
+    }
+
+    /// Point the entry at `addr`, keeping its flags. Bits of `addr` below 4K alignment can't be
+    /// stored, so are dropped rather than corrupting the flags.
+    pub fn set_addr(&mut self, addr: PhysAddr) {
+        self.inner = (self.inner & !ADDRESS_MASK) | (u64::from(addr) & ADDRESS_MASK);
+    }
+
+    pub fn set(&mut self, addr: PhysAddr, flags: EntryFlags) {
+        self.inner = (u64::from(addr) & ADDRESS_MASK) | flags.bits();
+    }
+}
+
+impl Entry<Level1> {
+    pub fn frame(&self) -> Option<AllocatedFrame<Size4K>> {
+        if self.flags().contains(EntryFlags::PRESENT) {
+            // Safety: The frame must've been allocated. This is safe if page -> frame mappings are
+            // created safely, because you can only map allocated frames
+            Some(unsafe { AllocatedFrame::containing_addr(self.addr()) })
+        } else {
+            None
+        }
+    }
+}
+
+impl Entry<Level2> {
+    pub fn frame(&self) -> Option<SizedAllocatedFrame<Size2M>> {
+        if self.flags().contains(EntryFlags::PRESENT) {
+            // Safety: The frame must've been allocated. This is safe if page -> frame mappings are
+            // created safely, because you can only map allocated frames
+            Some(unsafe {
+                SizedAllocatedFrame::new(self.addr(), self.flags().contains(EntryFlags::HUGE))
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Entry<Level3> {
+    pub fn frame(&self) -> Option<SizedAllocatedFrame<Size1G>> {
+        if self.flags().contains(EntryFlags::PRESENT) {
+            // Safety: The frame must've been allocated. This is safe if page -> frame mappings are
+            // created safely, because you can only map allocated frames
+            Some(unsafe {
+                SizedAllocatedFrame::new(self.addr(), self.flags().contains(EntryFlags::HUGE))
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Entry<Level4> {
+    pub fn frame(&self) -> Option<AllocatedFrame<Size4K>> {
+        if self.flags().contains(EntryFlags::PRESENT) {
+            // Safety: The frame must've been allocated. This is safe if page -> frame mappings are
+            // created safely, because you can only map allocated frames
+            Some(unsafe { AllocatedFrame::containing_addr(self.addr()) })
+        } else {
+            None
+        }
+    }
+}
+
+bitflags::bitflags! {
+    pub struct EntryFlags: u64 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const USER_ACCESSIBLE = 1 << 2;
+        const WRITE_THROUGH = 1 << 3;
+        const NO_CACHE = 1 << 4;
+        const ACCESSED = 1 << 5;
+        const DIRTY = 1 << 6;
+        const HUGE = 1 << 7;
+        const GLOBAL = 1 << 8;
+        const NO_EXEC = 1 << 63;
+    }
+}
+
+impl EntryFlags {
+    pub fn readable(self) -> bool {
+        self.contains(Self::PRESENT)
+    }
+
+    pub fn writable(self) -> bool {
+        self.contains(Self::PRESENT | Self::WRITABLE)
+    }
+
+    pub fn executable(self) -> bool {
+        self.contains(Self::PRESENT) && !self.contains(Self::NO_EXEC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+
+    fn random_addr(rng: &mut Rng) -> PhysAddr {
+        PhysAddr::new(rng.range(0..1 << PhysAddr::BITS) as usize)
+    }
+
+    #[test]
+    fn set_flags_keeps_addr() {
+        let mut entry = Entry::<Level1>::default();
+        entry.set(
+            PhysAddr::new(0x1234_5000),
+            EntryFlags::PRESENT | EntryFlags::WRITABLE,
+        );
+        entry.set_flags(EntryFlags::PRESENT | EntryFlags::NO_EXEC);
+        assert_eq!(entry.addr(), PhysAddr::new(0x1234_5000));
+        assert_eq!(entry.flags(), EntryFlags::PRESENT | EntryFlags::NO_EXEC);
+    }
+
+    #[test]
+    fn set_flags_on_unused_entry() {
+        let mut entry = Entry::<Level2>::default();
+        entry.set_flags(EntryFlags::PRESENT | EntryFlags::HUGE);
+        assert_eq!(entry.addr(), PhysAddr::new(0));
+        assert_eq!(entry.flags(), EntryFlags::PRESENT | EntryFlags::HUGE);
+        assert!(!entry.is_unused());
+    }
+
+    #[test]
+    fn set_addr_masks_addr() {
+        let mut entry = Entry::<Level1>::default();
+        entry.set(
+            PhysAddr::new(0x5000),
+            EntryFlags::PRESENT | EntryFlags::NO_EXEC,
+        );
+        entry.set_addr(PhysAddr::new(0x000f_1234_5678_9abc));
+        assert_eq!(entry.addr(), PhysAddr::new(0x000f_1234_5678_9000));
+        assert_eq!(entry.flags(), EntryFlags::PRESENT | EntryFlags::NO_EXEC);
+    }
+
+    #[test]
+    fn set_addr_keeps_flags() {
+        let mut rng = Rng::new();
+        for _ in 0..10_000 {
+            let flags = EntryFlags::from_bits_truncate(rng.next_u64());
+            let addr = random_addr(&mut rng);
+            let mut entry = Entry::<Level1>::default();
+            entry.set(random_addr(&mut rng), flags);
+
+            entry.set_addr(addr);
+            assert_eq!(entry.flags(), flags);
+            assert_eq!(u64::from(entry.addr()), u64::from(addr) & ADDRESS_MASK);
+        }
+    }
+
+    #[test]
+    fn set_flags_keeps_addr_random() {
+        let mut rng = Rng::new();
+        for _ in 0..10_000 {
+            let addr = random_addr(&mut rng);
+            let flags = EntryFlags::from_bits_truncate(rng.next_u64());
+            let mut entry = Entry::<Level1>::default();
+            entry.set(addr, EntryFlags::from_bits_truncate(rng.next_u64()));
+
+            entry.set_flags(flags);
+            assert_eq!(entry.flags(), flags);
+            assert_eq!(u64::from(entry.addr()), u64::from(addr) & ADDRESS_MASK);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Physical page frames, and the interface to the frame allocators.
+
+use core::{fmt, marker::PhantomData};
+
+use crate::{PhysAddr, Size4K, SizedRegion};
+
+/// A physical page frame that is guaranteed to be allocated.
+pub struct AllocatedFrame<S: SizedRegion> {
+    start: PhysAddr,
+    _marker: PhantomData<S>,
+}
+
+impl<S: SizedRegion> AllocatedFrame<S> {
+    /// Returns the frame containing `addr`.
+    ///
+    /// # Safety
+    ///
+    /// The frame must actually be allocated, and not owned by anything else.
+    pub unsafe fn containing_addr(addr: PhysAddr) -> Self {
+        Self {
+            start: addr.align_down(S::SIZE),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn start_address(&self) -> PhysAddr {
+        self.start
+    }
+}
+
+impl<S: SizedRegion> fmt::Debug for AllocatedFrame<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Frame")
+            .field(&self.start)
+            .field(&S::DISPLAY)
+            .finish()
+    }
+}
+
+pub trait FrameAllocator<S: SizedRegion> {
+    /// Whether [`deallocate`](Self::deallocate) is supported. Allocators that can't hand frames
+    /// out again set this to `false`, and leak the frames they're asked to deallocate.
+    const CAN_DEALLOCATE: bool = true;
+
+    /// Allocate a frame, returning `None` if there are no free frames of this size left.
+    fn allocate(&mut self) -> Option<AllocatedFrame<S>>;
+    /// Return a frame to the allocator so it can be handed out again.
+    fn deallocate(&mut self, frame: AllocatedFrame<S>);
+}
+
+/// An [`AllocatedFrame`] that is either a normal frame, or a "huge" frame of a certain size
+pub enum SizedAllocatedFrame<S: SizedRegion> {
+    Normal(AllocatedFrame<Size4K>),
+    Huge(AllocatedFrame<S>),
+}
+
+impl<S: SizedRegion> SizedAllocatedFrame<S> {
+    /// Returns the frame containing `addr`, which is a huge frame if `huge` is set.
+    ///
+    /// # Safety
+    ///
+    /// The frame must actually be allocated, and not owned by anything else.
+    pub unsafe fn new(addr: PhysAddr, huge: bool) -> Self {
+        if huge {
+            Self::Huge(AllocatedFrame::containing_addr(addr))
+        } else {
+            Self::Normal(AllocatedFrame::containing_addr(addr))
+        }
+    }
+
+    /// Return the frame to an allocator, whichever size it is.
+    pub fn deallocate<A>(self, allocator: &mut A)
+    where
+        A: FrameAllocator<Size4K> + FrameAllocator<S>,
+    {
+        match self {
+            Self::Normal(frame) => allocator.deallocate(frame),
+            Self::Huge(frame) => allocator.deallocate(frame),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Marker types for the levels of the page table hierarchy.
+
+/// Specifies a page table level
+pub trait PageTableLevel: Copy {}
+
+/// Specifies a page table level where the next level down is another page table
+pub trait HigherPageTableLevel: PageTableLevel {
+    /// Level of the next page table down
+    type NextLevel: PageTableLevel;
+}
+
+#[derive(Clone, Copy)]
+pub enum Level1 {}
+#[derive(Clone, Copy)]
+pub enum Level2 {}
+#[derive(Clone, Copy)]
+pub enum Level3 {}
+#[derive(Clone, Copy)]
+pub enum Level4 {}
+
+impl PageTableLevel for Level1 {}
+impl PageTableLevel for Level2 {}
+impl PageTableLevel for Level3 {}
+impl PageTableLevel for Level4 {}
+
+impl HigherPageTableLevel for Level2 {
+    type NextLevel = Level1;
+}
+
+impl HigherPageTableLevel for Level3 {
+    type NextLevel = Level2;
+}
+
+impl HigherPageTableLevel for Level4 {
+    type NextLevel = Level3;
+}
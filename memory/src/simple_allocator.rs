@@ -0,0 +1,240 @@
+//! A frame allocator that hands out each available frame in turn, and can't take them back.
+
+use core::ops::Range;
+
+use crate::{
+    frame::{AllocatedFrame, FrameAllocator},
+    PhysAddr, Size4K, SizedRegion,
+};
+
+/// A map of physical memory, E.G. from the bootloader.
+pub trait MemoryMap {
+    /// Returns the areas of memory available for use, in any order.
+    fn available_areas(&self) -> impl Iterator<Item = Range<PhysAddr>> + '_;
+}
+
+impl MemoryMap for &[Range<PhysAddr>] {
+    fn available_areas(&self) -> impl Iterator<Item = Range<PhysAddr>> + '_ {
+        self.iter().cloned()
+    }
+}
+
+/// Hands out the frames of the available memory in `memory_map` in order of address, skipping
+/// any that overlap the kernel or the multiboot information.
+#[derive(Clone)]
+pub struct SimpleFrameAllocator<M: MemoryMap> {
+    next: PhysAddr,
+    memory_map: M,
+    current_area: Option<Range<PhysAddr>>,
+    kernel: Range<PhysAddr>,
+    multiboot_info: Range<PhysAddr>,
+}
+
+impl<M: MemoryMap> SimpleFrameAllocator<M> {
+    pub fn new(memory_map: M, kernel: Range<PhysAddr>, multiboot_info: Range<PhysAddr>) -> Self {
+        let mut allocator = Self {
+            next: PhysAddr::NULL,
+            memory_map,
+            current_area: None,
+            kernel,
+            multiboot_info,
+        };
+        allocator.next_area();
+        allocator
+    }
+
+    /// Returns the number of frames still available for allocation. This walks the rest of the
+    /// memory map, so it's slow.
+    pub fn free_frames(&self) -> usize
+    where
+        M: Clone,
+    {
+        self.clone().count()
+    }
+
+    /// Move on to the lowest area that doesn't end before the next frame.
+    fn next_area(&mut self) {
+        let next = self.next;
+        self.current_area = self
+            .memory_map
+            .available_areas()
+            .filter(|area| area.start < area.end && area.end > next)
+            .min_by_key(|area| area.start);
+    }
+}
+
+impl<M: MemoryMap> Iterator for SimpleFrameAllocator<M> {
+    type Item = AllocatedFrame<Size4K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let area = self.current_area.clone()?;
+            let frame = self.next..self.next + Size4K::SIZE;
+
+            // Skip past whatever the frame overlaps. The kernel and the multiboot information may
+            // be next to each other, so check both again afterwards.
+            if let Some(reserved) = [&self.kernel, &self.multiboot_info]
+                .iter()
+                .find(|reserved| frame.start < reserved.end && reserved.start < frame.end)
+            {
+                self.next = reserved.end.align_up(Size4K::SIZE);
+            } else if frame.start < area.start {
+                self.next = area.start.align_up(Size4K::SIZE);
+            } else if frame.end > area.end {
+                // The rest of the area is smaller than a frame
+                self.next = area.end.align_up(Size4K::SIZE);
+                self.next_area();
+            } else {
+                self.next = frame.end;
+                // Safety: The frame is in available memory, and isn't reserved
+                return Some(unsafe { AllocatedFrame::containing_addr(frame.start) });
+            }
+        }
+    }
+}
+
+impl<M: MemoryMap> FrameAllocator<Size4K> for SimpleFrameAllocator<M> {
+    const CAN_DEALLOCATE: bool = false;
+
+    fn allocate(&mut self) -> Option<AllocatedFrame<Size4K>> {
+        self.next()
+    }
+
+    /// Frames can't be handed out again, so this leaks `frame` (see
+    /// [`CAN_DEALLOCATE`](FrameAllocator::CAN_DEALLOCATE)).
+    fn deallocate(&mut self, _frame: AllocatedFrame<Size4K>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::test_rng::Rng;
+
+    const K: usize = Size4K::SIZE;
+
+    fn frames(
+        areas: &[Range<PhysAddr>],
+        kernel: Range<PhysAddr>,
+        multiboot_info: Range<PhysAddr>,
+    ) -> Vec<usize> {
+        SimpleFrameAllocator::new(areas, kernel, multiboot_info)
+            .map(|frame| frame.start_address().as_usize())
+            .collect()
+    }
+
+    /// Returns whether the frame starting at `start` should be handed out, checked one frame at
+    /// a time.
+    fn is_free(start: usize, areas: &[Range<PhysAddr>], reserved: &[&Range<PhysAddr>]) -> bool {
+        let end = start + K;
+        areas
+            .iter()
+            .any(|area| area.start.as_usize() <= start && end <= area.end.as_usize())
+            && reserved.iter().all(|reserved| {
+                end <= reserved.start.as_usize() || reserved.end.as_usize() <= start
+            })
+    }
+
+    #[test]
+    fn skips_kernel_and_multiboot_info() {
+        let areas = [
+            PhysAddr::new(0)..PhysAddr::new(0x9fc00),
+            PhysAddr::new(0x10_0000)..PhysAddr::new(0x20_0000),
+        ];
+        // The multiboot information starts in the last page of the kernel, and ends part way
+        // through the next one
+        let kernel = PhysAddr::new(0x10_0000)..PhysAddr::new(0x13_0800);
+        let multiboot_info = PhysAddr::new(0x13_0900)..PhysAddr::new(0x13_1100);
+
+        let frames = frames(&areas, kernel, multiboot_info);
+        assert_eq!(frames.first(), Some(&0));
+        // The partial frame at the end of the first area is skipped
+        assert_eq!(frames[0x9e], 0x9_e000);
+        assert_eq!(frames[0x9f], 0x13_2000);
+        assert_eq!(frames.last(), Some(&0x1f_f000));
+        assert_eq!(frames.len(), 0x9f + (0x20_0000 - 0x13_2000) / K);
+    }
+
+    #[test]
+    fn unsorted_areas() {
+        let areas = [
+            PhysAddr::new(0x10_0000)..PhysAddr::new(0x10_2000),
+            PhysAddr::new(0x1000)..PhysAddr::new(0x3000),
+        ];
+        let nothing = PhysAddr::new(0)..PhysAddr::new(0);
+        assert_eq!(
+            frames(&areas, nothing.clone(), nothing),
+            [0x1000, 0x2000, 0x10_0000, 0x10_1000]
+        );
+    }
+
+    #[test]
+    fn deallocate_leaks() {
+        let areas = [PhysAddr::new(0)..PhysAddr::new(0x3000)];
+        let nothing = PhysAddr::new(0)..PhysAddr::new(0);
+        let mut allocator = SimpleFrameAllocator::new(&areas[..], nothing.clone(), nothing);
+        let frame = allocator.allocate().unwrap();
+        allocator.deallocate(frame);
+        // The frame at 0 isn't handed out again
+        let rest: Vec<_> = allocator.map(|frame| frame.start_address()).collect();
+        assert_eq!(rest, [PhysAddr::new(0x1000), PhysAddr::new(0x2000)]);
+    }
+
+    #[test]
+    fn free_frames() {
+        let areas = [PhysAddr::new(0)..PhysAddr::new(0x10_0000)];
+        let areas = &areas[..];
+        let mut allocator = SimpleFrameAllocator::new(
+            areas,
+            PhysAddr::new(0)..PhysAddr::new(0x1000),
+            PhysAddr::new(0)..PhysAddr::new(0),
+        );
+        assert_eq!(allocator.free_frames(), 0xff);
+        allocator.allocate();
+        assert_eq!(allocator.free_frames(), 0xfe);
+    }
+
+    #[test]
+    fn random_memory_maps() {
+        /// Size of the memory in the random maps.
+        const MEMORY: u64 = 16 * 1024 * 1024;
+        let mut rng = Rng::new();
+
+        for _ in 0..200 {
+            // Up to 8 areas with unaligned boundaries, which don't overlap
+            let mut boundaries: Vec<u64> = (0..rng.range(1..9) * 2)
+                .map(|_| rng.range(0..MEMORY))
+                .collect();
+            boundaries.sort_unstable();
+            let mut areas: Vec<Range<PhysAddr>> = boundaries
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .map(|&[start, end]| PhysAddr::new(start as usize)..PhysAddr::new(end as usize))
+                .collect();
+            // The memory map isn't necessarily sorted
+            areas.reverse();
+
+            let mut reserved = || {
+                let start = rng.range(0..MEMORY);
+                PhysAddr::new(start as usize)..PhysAddr::new(rng.range(start..MEMORY) as usize)
+            };
+            let kernel = reserved();
+            let multiboot_info = reserved();
+
+            let expected: Vec<usize> = (0..MEMORY as usize)
+                .step_by(K)
+                .filter(|&start| is_free(start, &areas, &[&kernel, &multiboot_info]))
+                .collect();
+            assert_eq!(
+                frames(&areas, kernel.clone(), multiboot_info.clone()),
+                expected,
+                "areas {:?}, kernel {:?}, multiboot information {:?}",
+                areas,
+                kernel,
+                multiboot_info
+            );
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! A small pseudo-random number generator for property tests, so they need no dependencies.
+
+/// An xorshift64* generator. It always starts from the same seed, so failures are reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number in `range`.
+    pub fn range(&mut self, range: core::ops::Range<u64>) -> u64 {
+        range.start + self.next_u64() % (range.end - range.start)
+    }
+}
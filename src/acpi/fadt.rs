@@ -0,0 +1,47 @@
+use super::find_table;
+
+/// Offset of the RTC century register index in the FADT, after the header.
+const CENTURY_OFFSET: usize = 72;
+
+/// The Fixed ACPI Description Table, describing fixed hardware features. Only the parts we use
+/// are parsed.
+pub struct Fadt {
+    century_register: Option<u8>,
+}
+
+impl Fadt {
+    /// Find the FADT, if the firmware provides one.
+    pub fn get() -> Option<Self> {
+        Some(Self::parse(find_table("FACP")?.data()))
+    }
+
+    /// Parse the contents of the FADT, after the header.
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            // 0 means there's no century register. Very old FADTs are too short to have the field
+            century_register: data.get(CENTURY_OFFSET).copied().filter(|&reg| reg != 0),
+        }
+    }
+
+    /// The index of the CMOS register holding the century of the RTC's date, if there is one.
+    pub fn century_register(&self) -> Option<u8> {
+        self.century_register
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn century_register() {
+        let mut data = [0; 244 - 36];
+        assert_eq!(Fadt::parse(&data).century_register(), None);
+        data[CENTURY_OFFSET] = 0x32;
+        assert_eq!(Fadt::parse(&data).century_register(), Some(0x32));
+        assert_eq!(
+            Fadt::parse(&data[..CENTURY_OFFSET]).century_register(),
+            None
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use log::warn;
+
+use super::find_table;
+use crate::memory::PhysAddr;
+
+/// The Multiple APIC Description Table, describing the interrupt controllers in the system.
+pub struct Madt {
+    /// Physical address of the local APIC of each processor, unless overridden. `None` if the
+    /// firmware gave an invalid address.
+    local_apic_addr: Option<PhysAddr>,
+    /// Whether the system also has legacy 8259 PICs, which must be masked to use the APICs.
+    pub has_8259: bool,
+    entries: Vec<MadtEntry>,
+}
+
+/// An interrupt controller structure from the [`Madt`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    /// A processor and its local APIC.
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    /// An I/O APIC, handling the global system interrupts starting at `gsi_base`.
+    IoApic {
+        id: u8,
+        addr: PhysAddr,
+        gsi_base: u32,
+    },
+    /// An ISA IRQ that isn't identity mapped to a global system interrupt, or that isn't
+    /// active-high and edge-triggered.
+    InterruptOverride { irq: u8, gsi: u32, flags: u16 },
+    /// A 64-bit address of the local APICs, overriding the 32-bit one in the MADT header.
+    LocalApicAddrOverride(PhysAddr),
+    /// A processor with an x2APIC ID too large for [`MadtEntry::LocalApic`].
+    LocalX2Apic {
+        processor_uid: u32,
+        x2apic_id: u32,
+        flags: u32,
+    },
+    /// An entry type we don't handle.
+    Unknown(u8),
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Returns the firmware-supplied `addr` of `what` as a physical address, or `None` (with a warning)
+/// if it isn't a valid one.
+fn phys_addr(addr: u64, what: &str) -> Option<PhysAddr> {
+    let valid = PhysAddr::try_new(addr as usize);
+    if valid.is_none() {
+        warn!("Ignoring {} with invalid address {:#x}", what, addr);
+    }
+    valid
+}
+
+/// Parse the interrupt controller structure in `entry`, which starts with its type and length.
+/// Returns `None` if it has an invalid address.
+fn parse_entry(entry: &[u8]) -> Option<MadtEntry> {
+    Some(match entry[0] {
+        0 => MadtEntry::LocalApic {
+            processor_id: entry[2],
+            apic_id: entry[3],
+            flags: read_u32(entry, 4),
+        },
+        1 => MadtEntry::IoApic {
+            id: entry[2],
+            addr: phys_addr(read_u32(entry, 4) as u64, "I/O APIC")?,
+            gsi_base: read_u32(entry, 8),
+        },
+        2 => MadtEntry::InterruptOverride {
+            irq: entry[3],
+            gsi: read_u32(entry, 4),
+            flags: read_u16(entry, 8),
+        },
+        5 => MadtEntry::LocalApicAddrOverride(phys_addr(
+            read_u64(entry, 4),
+            "local APIC address override",
+        )?),
+        9 => MadtEntry::LocalX2Apic {
+            x2apic_id: read_u32(entry, 4),
+            flags: read_u32(entry, 8),
+            processor_uid: read_u32(entry, 12),
+        },
+        typ => MadtEntry::Unknown(typ),
+    })
+}
+
+impl Madt {
+    /// Find the MADT, if the firmware provides one. Entries with invalid addresses are skipped.
+    pub fn get() -> Option<Self> {
+        let data = find_table("APIC")?.data();
+
+        let mut entries = Vec::new();
+        let mut rest = &data[8..];
+        // Each entry starts with its type and length
+        while rest.len() >= 2 && rest.len() >= rest[1] as usize && rest[1] >= 2 {
+            let (entry, next) = rest.split_at(rest[1] as usize);
+            rest = next;
+            entries.extend(parse_entry(entry));
+        }
+
+        Some(Self {
+            local_apic_addr: phys_addr(read_u32(data, 0) as u64, "local APIC"),
+            has_8259: read_u32(data, 4) & 1 != 0,
+            entries,
+        })
+    }
+
+    /// Iterate over the interrupt controller structures.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// The physical address of the local APICs, taking overrides into account. Returns `None` if
+    /// the firmware didn't give a valid one.
+    pub fn local_apic_addr(&self) -> Option<PhysAddr> {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddrOverride(addr) => Some(addr),
+                _ => None,
+            })
+            .or(self.local_apic_addr)
+    }
+}
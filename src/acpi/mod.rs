@@ -0,0 +1,138 @@
+//! Just enough ACPI to find the firmware's system description tables, such as the [`Madt`].
+
+mod fadt;
+mod madt;
+pub use fadt::Fadt;
+pub use madt::{Madt, MadtEntry};
+
+use alloc::vec::Vec;
+use core::{mem::size_of, slice, str};
+
+use log::warn;
+use spin::Once;
+
+use crate::memory::{paging::EntryFlags, PhysAddr};
+
+/// The header shared by all the system description tables.
+#[derive(Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// The 4 character signature identifying the type of the table, E.G. "APIC" for the MADT.
+    pub fn signature(&self) -> &str {
+        str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    /// Length of the whole table, including the header.
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    /// The whole table, including the header.
+    fn bytes(&self) -> &[u8] {
+        // Safety: The whole table is mapped by `map_table`
+        unsafe { slice::from_raw_parts(self as *const _ as *const u8, self.length()) }
+    }
+
+    /// The contents of the table, after the header.
+    pub fn data(&self) -> &[u8] {
+        &self.bytes()[size_of::<Self>()..]
+    }
+
+    fn checksum_is_valid(&self) -> bool {
+        self.bytes().iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+    }
+}
+
+/// The tables listed by the RSDT or XSDT.
+static TABLES: Once<Vec<&'static SdtHeader>> = Once::new();
+
+/// Find the ACPI tables through the RSDP passed by the bootloader, and map them.
+pub fn init(mb: &multiboot2::BootInformation) {
+    let (root_addr, entry_size) = if let Some(rsdp) = mb.rsdp_v2_tag() {
+        assert!(rsdp.checksum_is_valid(), "Invalid RSDP checksum");
+        (rsdp.xsdt_address(), size_of::<u64>())
+    } else if let Some(rsdp) = mb.rsdp_v1_tag() {
+        assert!(rsdp.checksum_is_valid(), "Invalid RSDP checksum");
+        (rsdp.rsdt_address(), size_of::<u32>())
+    } else {
+        warn!("No ACPI RSDP found");
+        return;
+    };
+
+    let root_addr = match PhysAddr::try_new(root_addr) {
+        Some(addr) => addr,
+        None => {
+            warn!("Invalid ACPI root table address {:#x}", root_addr);
+            return;
+        }
+    };
+    // Safety: The RSDP points at the root table
+    let root = unsafe { map_table(root_addr) };
+    assert!(root.checksum_is_valid(), "Invalid ACPI root table checksum");
+
+    let tables = root
+        .data()
+        .chunks_exact(entry_size)
+        .filter_map(|entry| {
+            let mut addr = [0; size_of::<u64>()];
+            addr[..entry_size].copy_from_slice(entry);
+            let addr = u64::from_le_bytes(addr);
+            match PhysAddr::try_new(addr as usize) {
+                // Safety: The root table only points at other tables
+                Some(addr) => Some(unsafe { map_table(addr) }),
+                None => {
+                    warn!("Ignoring ACPI table with invalid address {:#x}", addr);
+                    None
+                }
+            }
+        })
+        .filter(|table| {
+            let valid = table.checksum_is_valid();
+            if !valid {
+                warn!(
+                    "Ignoring ACPI {} table with invalid checksum",
+                    table.signature()
+                );
+            }
+            valid
+        })
+        .collect();
+    TABLES.call_once(|| tables);
+}
+
+/// Find the table with the given `signature`.
+pub fn find_table(signature: &str) -> Option<&'static SdtHeader> {
+    TABLES
+        .get()?
+        .iter()
+        .copied()
+        .find(|table| table.signature() == signature)
+}
+
+/// Identity map the ACPI table at `addr`.
+///
+/// # Safety
+///
+/// `addr` must be the physical address of an ACPI table.
+unsafe fn map_table(addr: PhysAddr) -> &'static SdtHeader {
+    let flags = EntryFlags::NO_EXEC;
+    let mut controller = crate::memory::controller();
+    // Map the header first, to find out how long the table is
+    let table = &*controller
+        .identity_map(addr..addr + size_of::<SdtHeader>(), flags)
+        .as_ptr::<SdtHeader>();
+    controller.identity_map(addr..addr + table.length(), flags);
+    table
+}
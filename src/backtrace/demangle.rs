@@ -0,0 +1,148 @@
+//! Demangling of Rust's legacy symbol names, E.G. `_ZN3rux4init11kernel_main17h0123456789abcdefE`
+//! to `rux::init::kernel_main`. The kernel is built with `-C symbol-mangling-version=legacy` (see
+//! `.cargo/config.toml`), so that's the only scheme that needs handling. The few shims rustc
+//! generates itself (E.G. `__rust_alloc`) are always mangled with the v0 scheme, and are shown
+//! as they are.
+
+use core::fmt::{self, Write};
+
+/// A symbol name, displayed demangled if it is a mangled Rust name, and as it is otherwise.
+pub struct Demangle<'a>(pub &'a str);
+
+/// Split the next `<length><identifier>` component off the front of `rest`.
+fn next_component<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let len: usize = rest[..digits].parse().ok()?;
+    let component = rest.get(digits..digits + len)?;
+    *rest = &rest[digits + len..];
+    Some(component)
+}
+
+/// Returns whether `component` is the hash rustc appends to every name, E.G. `h0123456789abcdef`.
+fn is_hash(component: &str) -> bool {
+    component.len() == 17
+        && component.starts_with('h')
+        && component[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Write an identifier, decoding the escapes used for characters that aren't allowed in symbols.
+fn write_identifier(f: &mut fmt::Formatter<'_>, identifier: &str) -> fmt::Result {
+    let mut rest = if identifier.starts_with("_$") {
+        &identifier[1..]
+    } else {
+        identifier
+    };
+    while !rest.is_empty() {
+        if let Some(escaped) = rest.strip_prefix('$') {
+            let end = match escaped.find('$') {
+                Some(end) => end,
+                None => return f.write_str(rest),
+            };
+            let c = match &escaped[..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                code => match code
+                    .strip_prefix('u')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => c,
+                    None => return f.write_str(rest),
+                },
+            };
+            f.write_char(c)?;
+            rest = &escaped[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = after;
+        } else {
+            let end = rest[1..].find(['$', '.']).map_or(rest.len(), |end| end + 1);
+            f.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // LLVM may add a suffix to names it has duplicated, E.G. `.llvm.1234`
+        let name = self.0.split(".llvm.").next().unwrap_or(self.0);
+        let inner = match name
+            .strip_prefix("_ZN")
+            .and_then(|name| name.strip_suffix('E'))
+        {
+            Some(inner) => inner,
+            None => return f.write_str(self.0),
+        };
+
+        // Check the whole name parses before writing any of it
+        let mut rest = inner;
+        while !rest.is_empty() {
+            if next_component(&mut rest).is_none() {
+                return f.write_str(self.0);
+            }
+        }
+
+        let mut rest = inner;
+        let mut first = true;
+        while let Some(component) = next_component(&mut rest) {
+            if rest.is_empty() && is_hash(component) {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_identifier(f, component)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String};
+
+    use super::*;
+
+    fn demangle(name: &str) -> String {
+        format!("{}", Demangle(name))
+    }
+
+    #[test_case]
+    fn legacy_names() {
+        assert_eq!(
+            demangle("_ZN3rux4init11kernel_main17h0123456789abcdefE"),
+            "rux::init::kernel_main"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE"
+            ),
+            "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+        );
+        assert_eq!(
+            demangle("_ZN3rux6memory4heap17h0123456789abcdefE.llvm.1234"),
+            "rux::memory::heap"
+        );
+    }
+
+    #[test_case]
+    fn other_names_are_unchanged() {
+        for name in [
+            "memcpy",
+            "_ZN3rux4init",
+            "_ZN3rux99initE",
+            "_RNvCs1234_3rux10kernel_main",
+        ] {
+            assert_eq!(demangle(name), name);
+        }
+    }
+}
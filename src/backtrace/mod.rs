@@ -0,0 +1,107 @@
+//! Stack backtraces, found by following the chain of frame pointers (the kernel is built with
+//! `-C force-frame-pointers=yes`), and symbolised using the kernel's ELF symbol table.
+
+mod demangle;
+mod symbols;
+
+use core::{arch::asm, fmt, mem::size_of};
+
+pub use demangle::Demangle;
+pub use symbols::{init, resolve};
+
+use crate::{
+    memory::{paging::is_mapped, VirtAddr},
+    println,
+};
+
+/// Maximum number of frames printed, in case the frame pointers are corrupted and form a loop.
+const MAX_FRAMES: usize = 64;
+
+/// An address, displayed with the function it is in, E.G.
+/// `0x0000000000105abc rux::init::kernel_main+0x1c`.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+        match resolve(self.0) {
+            Some(symbol) => write!(f, " {}+{:#x}", Demangle(symbol.name), symbol.offset),
+            None => write!(f, " <unknown>"),
+        }
+    }
+}
+
+/// Returns whether the 8 bytes at `addr` can be read without faulting.
+fn is_readable(addr: usize) -> bool {
+    addr.is_multiple_of(size_of::<usize>()) && VirtAddr::try_new(addr).is_some_and(is_mapped)
+}
+
+/// Returns the return addresses of the frames starting at frame pointer `rbp`, from the innermost
+/// outwards.
+fn return_addresses(mut rbp: usize) -> impl Iterator<Item = usize> {
+    core::iter::from_fn(move || {
+        // Each frame starts with the caller's frame pointer, followed by the return address
+        if rbp == 0 || !is_readable(rbp) || !is_readable(rbp + size_of::<usize>()) {
+            return None;
+        }
+        // Safety: Both words of the frame were just checked to be mapped
+        let (next, return_address) = unsafe {
+            let frame = rbp as *const usize;
+            (frame.read(), frame.add(1).read())
+        };
+        // The stack grows down, so callers' frames are always higher up
+        rbp = if next > rbp { next } else { 0 };
+        (return_address != 0).then_some(return_address)
+    })
+    .take(MAX_FRAMES)
+}
+
+/// Print a backtrace of the current stack.
+pub fn print_backtrace() {
+    let rbp: usize;
+    // Safety: This only reads the frame pointer
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+
+    println!("Backtrace:");
+    for (i, return_address) in return_addresses(rbp).enumerate() {
+        // Return addresses point after the call, which may be the start of the next function
+        let caller = return_address - 1;
+        match resolve(caller) {
+            Some(symbol) => {
+                println!(
+                    "  {:2}: {:#018x} {}+{:#x}",
+                    i,
+                    return_address,
+                    Demangle(symbol.name),
+                    symbol.offset + 1
+                );
+            }
+            None => {
+                println!("  {:2}: {:#018x} <unknown>", i, return_address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    #[inline(never)]
+    fn marker() {}
+
+    #[test_case]
+    fn resolves_and_demangles_symtab_names() {
+        let symbol = resolve(marker as fn() as usize).expect("marker isn't in the symbol table");
+        assert_eq!(symbol.offset, 0);
+        assert_eq!(
+            format!("{}", Demangle(symbol.name)),
+            "rux::backtrace::tests::marker",
+            "mangled as {}",
+            symbol.name
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Looking up addresses in the kernel's ELF symbol table.
+
+use core::{mem::size_of, slice, str};
+
+use multiboot2::ElfSectionType;
+use spin::Once;
+
+/// An entry in an ELF64 symbol table.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ElfSymbol {
+    /// Offset of the name in the string table.
+    name: u32,
+    info: u8,
+    other: u8,
+    section: u16,
+    value: u64,
+    size: u64,
+}
+
+/// Symbol type of functions, in the low bits of [`ElfSymbol::info`].
+const SYMBOL_TYPE_FUNC: u8 = 2;
+
+impl ElfSymbol {
+    fn is_function(&self) -> bool {
+        self.info & 0xf == SYMBOL_TYPE_FUNC
+    }
+}
+
+struct SymbolTable {
+    symbols: &'static [ElfSymbol],
+    strings: &'static [u8],
+}
+
+static SYMBOL_TABLE: Once<SymbolTable> = Once::new();
+
+/// A function containing an address.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    /// The mangled name of the function.
+    pub name: &'static str,
+    /// Offset of the address from the start of the function.
+    pub offset: usize,
+}
+
+/// Find the kernel's symbol and string tables. GRUB loads them along with the rest of the kernel,
+/// and they must be mapped by the time this is called.
+pub fn init(mb: &multiboot2::BootInformation) {
+    let sections = || {
+        mb.elf_sections_tag()
+            .expect("Multiboot2 ELF sections tag required")
+            .sections()
+            .filter(|section| section.start_address() != 0)
+    };
+    let symtab =
+        sections().find(|section| section.section_type() == ElfSectionType::LinkerSymbolTable);
+    let strtab = sections().find(|section| {
+        section.section_type() == ElfSectionType::StringTable && section.name() == ".strtab"
+    });
+
+    if let (Some(symtab), Some(strtab)) = (symtab, strtab) {
+        // Safety: The sections were loaded by the bootloader, and are never modified
+        let table = unsafe {
+            SymbolTable {
+                symbols: slice::from_raw_parts(
+                    symtab.start_address() as *const ElfSymbol,
+                    symtab.size() as usize / size_of::<ElfSymbol>(),
+                ),
+                strings: slice::from_raw_parts(
+                    strtab.start_address() as *const u8,
+                    strtab.size() as usize,
+                ),
+            }
+        };
+        SYMBOL_TABLE.call_once(|| table);
+    }
+}
+
+/// Find the function containing `addr`, if the symbol table has been loaded.
+pub fn resolve(addr: usize) -> Option<Symbol> {
+    let table = SYMBOL_TABLE.get()?;
+    let addr = addr as u64;
+    let symbol = table
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.is_function() && symbol.value <= addr)
+        .filter(|symbol| symbol.size == 0 || addr < symbol.value + symbol.size)
+        .max_by_key(|symbol| symbol.value)?;
+
+    let name = table.strings.get(symbol.name as usize..)?;
+    let len = name.iter().position(|&byte| byte == 0)?;
+    Some(Symbol {
+        name: str::from_utf8(&name[..len]).ok()?,
+        offset: (addr - symbol.value) as usize,
+    })
+}
@@ -0,0 +1,399 @@
+//! The kernel message buffer, keeping the most recent log messages and console output in memory
+//! so they can be read back later (like `dmesg`), even if they've scrolled off the screen.
+//!
+//! The buffer is statically allocated, so messages can be stored before the heap is set up. Each
+//! message is given a sequence number, so readers can pick up where they left off. Log records are
+//! stored as one message each, and text printed with [`print!`](crate::print) is stored a line at
+//! a time.
+
+use core::{fmt, str};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    output::{serial, vga},
+    println,
+};
+
+/// Size of the buffer, in bytes. The oldest messages are dropped to make room for new ones.
+const BUFFER_SIZE: usize = 64 * 1024;
+/// Maximum length of a message, in bytes. Longer messages are truncated.
+pub const MAX_MESSAGE: usize = 512;
+/// Size of the length stored before each message.
+const HEADER_SIZE: usize = 2;
+
+/// A ring of messages, each stored as a little endian `u16` length followed by its text. Messages
+/// may wrap around the end of the buffer.
+struct Dmesg {
+    buffer: [u8; BUFFER_SIZE],
+    /// Offset of the oldest message.
+    head: usize,
+    /// Number of bytes in use.
+    used: usize,
+    /// Sequence number of the oldest message.
+    first_seq: u64,
+    /// Sequence number the next message will get.
+    next_seq: u64,
+}
+
+/// How far a reader has got: the sequence number of the next message to read, and the offset it's
+/// stored at, if known. The offset saves walking the buffer from the oldest message on every read.
+#[derive(Debug, Clone, Copy)]
+struct Cursor {
+    seq: u64,
+    offset: Option<usize>,
+}
+
+impl Cursor {
+    /// A cursor starting at sequence number `seq`, whose offset isn't known.
+    fn new(seq: u64) -> Self {
+        Self { seq, offset: None }
+    }
+}
+
+static DMESG: Mutex<Dmesg> = Mutex::new(Dmesg {
+    buffer: [0; BUFFER_SIZE],
+    head: 0,
+    used: 0,
+    first_seq: 0,
+    next_seq: 0,
+});
+
+impl Dmesg {
+    /// Copy `out.len()` bytes starting at `offset` out of the ring.
+    fn read_bytes(&self, offset: usize, out: &mut [u8]) {
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = self.buffer[(offset + i) % BUFFER_SIZE];
+        }
+    }
+
+    /// Copy `bytes` into the ring, starting at `offset`.
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.buffer[(offset + i) % BUFFER_SIZE] = byte;
+        }
+    }
+
+    /// Returns the length of the message at `offset`.
+    fn message_len(&self, offset: usize) -> usize {
+        let mut len = [0; HEADER_SIZE];
+        self.read_bytes(offset, &mut len);
+        u16::from_le_bytes(len) as usize
+    }
+
+    /// Drop the oldest message.
+    fn drop_oldest(&mut self) {
+        let size = HEADER_SIZE + self.message_len(self.head);
+        self.head = (self.head + size) % BUFFER_SIZE;
+        self.used -= size;
+        self.first_seq += 1;
+    }
+
+    fn push(&mut self, text: &[u8]) -> u64 {
+        let size = HEADER_SIZE + text.len();
+        while BUFFER_SIZE - self.used < size {
+            self.drop_oldest();
+        }
+        let tail = (self.head + self.used) % BUFFER_SIZE;
+        self.write_bytes(tail, &(text.len() as u16).to_le_bytes());
+        self.write_bytes(tail + HEADER_SIZE, text);
+        self.used += size;
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Copy the first message with a sequence number of at least `cursor.seq` into `out`,
+    /// returning its sequence number and length, and move the cursor past it.
+    fn read(&self, cursor: &mut Cursor, out: &mut [u8; MAX_MESSAGE]) -> Option<(u64, usize)> {
+        if cursor.seq >= self.next_seq {
+            return None;
+        }
+        let (mut current, mut offset) = match cursor.offset {
+            // Messages don't move, so the offset is right until the message is dropped
+            Some(offset) if cursor.seq >= self.first_seq => (cursor.seq, offset),
+            _ => (self.first_seq, self.head),
+        };
+        while current < cursor.seq {
+            offset = (offset + HEADER_SIZE + self.message_len(offset)) % BUFFER_SIZE;
+            current += 1;
+        }
+        let len = self.message_len(offset);
+        self.read_bytes(offset + HEADER_SIZE, &mut out[..len]);
+
+        *cursor = Cursor {
+            seq: current + 1,
+            offset: Some((offset + HEADER_SIZE + len) % BUFFER_SIZE),
+        };
+        Some((current, len))
+    }
+}
+
+/// Formats a message into a fixed-size buffer, truncating it at a character boundary if it's too
+/// long.
+pub struct MessageBuilder {
+    buffer: [u8; MAX_MESSAGE],
+    len: usize,
+}
+
+impl MessageBuilder {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_MESSAGE],
+            len: 0,
+        }
+    }
+
+    /// Store the message in the kernel message buffer, returning its sequence number.
+    pub fn finish(self) -> u64 {
+        // Interrupts are disabled so an interrupt handler logging can't deadlock with us
+        interrupts::without_interrupts(|| DMESG.lock().push(&self.buffer[..self.len]))
+    }
+}
+
+impl fmt::Write for MessageBuilder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(MAX_MESSAGE - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl MessageBuilder {
+    /// Erase the last character, like a backspace does on the console.
+    fn backspace(&mut self) {
+        while self.len > 0 {
+            self.len -= 1;
+            // Stop at the first byte of a UTF-8 sequence
+            if self.buffer[self.len] & 0xc0 != 0x80 {
+                break;
+            }
+        }
+    }
+}
+
+/// The line being printed with `print!`, until its newline is printed.
+static PENDING: Mutex<MessageBuilder> = Mutex::new(MessageBuilder::new());
+
+/// Adds printed text to the pending line, storing each line in the buffer once it's complete.
+struct LineWriter<'a> {
+    pending: &'a mut MessageBuilder,
+    dmesg: &'a mut Dmesg,
+}
+
+impl fmt::Write for LineWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                let pending = core::mem::replace(self.pending, MessageBuilder::new());
+                self.dmesg.push(&pending.buffer[..pending.len]);
+            }
+            for (j, text) in line.split('\x08').enumerate() {
+                if j > 0 {
+                    self.pending.backspace();
+                }
+                self.pending.write_str(text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Record text printed to the console, from [`print!`](crate::print).
+///
+/// The text is dropped if the buffer is in use by the code that was interrupted, E.G. when
+/// panicking while storing a message, rather than deadlocking.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use fmt::Write;
+
+    interrupts::without_interrupts(|| {
+        if let (Some(mut pending), Some(mut dmesg)) = (PENDING.try_lock(), DMESG.try_lock()) {
+            let _ = LineWriter {
+                pending: &mut pending,
+                dmesg: &mut dmesg,
+            }
+            .write_fmt(args);
+        }
+    });
+}
+
+/// Store `text` in the kernel message buffer, returning its sequence number.
+#[allow(dead_code)]
+pub fn push(text: &str) -> u64 {
+    use fmt::Write;
+
+    let mut message = MessageBuilder::new();
+    message.write_str(text).unwrap();
+    message.finish()
+}
+
+/// Call `f` with the sequence number and text of each message still in the buffer, starting with
+/// sequence number `seq` (or the oldest message, if that has been dropped). Returns the sequence
+/// number to continue from next time.
+///
+/// The buffer isn't locked while `f` runs, so it may log or print messages itself. Messages stored
+/// after `read_from` is called aren't passed to `f`, so it can't keep itself going forever.
+pub fn read_from(seq: u64, mut f: impl FnMut(u64, &str)) -> u64 {
+    let mut cursor = Cursor::new(seq);
+    let mut text = [0; MAX_MESSAGE];
+    let end = interrupts::without_interrupts(|| DMESG.lock().next_seq);
+    loop {
+        let message = interrupts::without_interrupts(|| {
+            let dmesg = DMESG.lock();
+            (cursor.seq < end)
+                .then(|| dmesg.read(&mut cursor, &mut text))
+                .flatten()
+        });
+        match message {
+            Some((message_seq, len)) => {
+                // Messages are truncated at character boundaries, so are always valid UTF-8
+                f(
+                    message_seq,
+                    str::from_utf8(&text[..len]).unwrap_or("<invalid>"),
+                );
+            }
+            None => return cursor.seq,
+        }
+    }
+}
+
+/// Make the buffer readable with the `dmesg` shell command.
+pub fn init() {
+    crate::shell::register_command(
+        "dmesg",
+        "dmesg [seq]: Show the kernel messages, from sequence number seq on",
+        dmesg_command,
+    );
+}
+
+fn dmesg_command(args: &[&str]) {
+    let seq = match args {
+        [] => Ok(0),
+        [seq] => seq.parse(),
+        _ => {
+            println!("Usage: dmesg [seq]");
+            return;
+        }
+    };
+    match seq {
+        Ok(seq) => {
+            read_from(seq, |seq, text| {
+                // Not printed with `println!`, which would store the messages again
+                vga::_print(format_args!("<{}> {}\n", seq, text));
+                serial::_print(format_args!("<{}> {}\n", seq, text));
+            });
+        }
+        Err(e) => {
+            println!("Invalid sequence number: {}", e);
+        }
+    }
+}
+
+/// Write every message still in the buffer to the serial port, from the panic handler.
+///
+/// This doesn't wait for the buffer or the serial port to be unlocked, as whatever locked them
+/// may have panicked.
+pub fn flush_to_serial() {
+    use fmt::Write;
+
+    let dmesg = match DMESG.try_lock() {
+        Some(dmesg) => dmesg,
+        None => return,
+    };
+    // Safety: The kernel is about to halt, so nothing else will use the port
+    let mut serial = unsafe { crate::output::serial::panic_port() };
+
+    let _ = writeln!(serial, "--- Kernel messages ---");
+    let mut text = [0; MAX_MESSAGE];
+    let mut cursor = Cursor::new(dmesg.first_seq);
+    while let Some((seq, len)) = dmesg.read(&mut cursor, &mut text) {
+        let text = str::from_utf8(&text[..len]).unwrap_or("<invalid>");
+        let _ = writeln!(serial, "<{}> {}", seq, text);
+    }
+    let _ = writeln!(serial, "--- End of kernel messages ---");
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+
+    use super::*;
+    use crate::print;
+
+    /// A buffer separate from the kernel's, so the tests control what's in it. It's too big to
+    /// put on the stack.
+    static TEST_DMESG: Mutex<Dmesg> = Mutex::new(Dmesg {
+        buffer: [0; BUFFER_SIZE],
+        head: 0,
+        used: 0,
+        first_seq: 0,
+        next_seq: 0,
+    });
+
+    /// Returns the text of message `seq`, with a length that varies from message to message.
+    fn message(seq: u64, out: &mut [u8; MAX_MESSAGE]) -> &[u8] {
+        let len = (seq as usize * 37) % MAX_MESSAGE;
+        for (i, byte) in out[..len].iter_mut().enumerate() {
+            *byte = b'a' + ((seq as usize + i) % 26) as u8;
+        }
+        &out[..len]
+    }
+
+    #[test_case]
+    fn cursor_follows_the_ring() {
+        let mut dmesg = TEST_DMESG.lock();
+        let mut expected = [0; MAX_MESSAGE];
+        let mut text = [0; MAX_MESSAGE];
+        let mut cursor = Cursor::new(0);
+
+        // Read as messages are added, until the buffer has wrapped around several times
+        for seq in 0..2000 {
+            assert_eq!(dmesg.push(message(seq, &mut expected)), seq);
+            if seq % 7 == 0 {
+                while let Some((read_seq, len)) = dmesg.read(&mut cursor, &mut text) {
+                    assert!(read_seq >= dmesg.first_seq);
+                    assert_eq!(&text[..len], message(read_seq, &mut expected));
+                }
+                assert_eq!(cursor.seq, seq + 1);
+            }
+        }
+        assert!(
+            dmesg.first_seq > 0,
+            "The buffer should have dropped messages"
+        );
+
+        // A cursor for a dropped message starts again at the oldest one
+        let mut cursor = Cursor::new(0);
+        let (seq, _) = dmesg.read(&mut cursor, &mut text).unwrap();
+        assert_eq!(seq, dmesg.first_seq);
+
+        // A stale cursor, whose message has since been dropped, does the same
+        let mut stale = Cursor {
+            seq: 0,
+            offset: Some(dmesg.head),
+        };
+        let (seq, len) = dmesg.read(&mut stale, &mut text).unwrap();
+        assert_eq!(seq, dmesg.first_seq);
+        assert_eq!(&text[..len], message(seq, &mut expected));
+    }
+
+    #[test_case]
+    fn printed_lines_are_stored() {
+        let seq = read_from(0, |_, _| {});
+        println!("dmesg test {}", 1);
+        print!("dmesg test ");
+        print!("2xy\x08\x08\n");
+
+        let mut lines = Vec::new();
+        read_from(seq, |_, text| lines.push(String::from(text)));
+        assert!(lines.iter().any(|line| line == "dmesg test 1"));
+        assert!(lines.iter().any(|line| line == "dmesg test 2"));
+    }
+}
@@ -0,0 +1,522 @@
+//! A stub for the GDB remote serial protocol on the second serial port, so the kernel can be
+//! debugged from GDB on another machine. In QEMU, E.G.:
+//!
+//! ```text
+//! make run run_flags="-serial stdio -serial tcp::1234,server,nowait"
+//! gdb build/kernel-dev-x86_64.bin -ex "target remote :1234"
+//! ```
+//!
+//! The stub takes over the breakpoint and debug exceptions, and talks to GDB with interrupts
+//! disabled until it is told to resume. Only software breakpoints are supported. They are written
+//! into memory while the kernel runs, and removed whenever it stops, so GDB only ever sees the
+//! original code.
+
+mod packet;
+
+use core::{fmt::Write, ptr};
+
+use log::info;
+use spin::Mutex;
+use x86_64::{
+    instructions::segmentation::{Segment, DS, ES, FS, GS},
+    registers::control::{Cr0, Cr0Flags},
+    structures::idt::{InterruptStackFrame, InterruptStackFrameValue},
+    VirtAddr,
+};
+
+use self::packet::{decode_hex, parse_hex, Packet, PACKET_SIZE};
+use crate::{
+    interrupts::exceptions::{
+        set_exception_handler, Exception, ExceptionContext, GeneralRegisters,
+    },
+    memory::{self, paging, Size4K, SizedRegion},
+    output::serial::COM2,
+};
+
+/// Maximum number of breakpoints set at once.
+const MAX_BREAKPOINTS: usize = 32;
+/// The `int3` instruction, written over the first byte of an instruction to set a breakpoint.
+const INT3: u8 = 0xcc;
+/// Bit of RFLAGS which raises a debug exception after the next instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+/// Signal reported to GDB for every stop.
+const SIGTRAP: u8 = 5;
+/// Number of registers in GDB's order for x86-64: `rax`, `rbx`, `rcx`, `rdx`, `rsi`, `rdi`,
+/// `rbp`, `rsp`, `r8`-`r15` and `rip` (8 bytes each), then `eflags`, `cs`, `ss`, `ds`, `es`,
+/// `fs` and `gs` (4 bytes each).
+const REGISTER_COUNT: usize = 24;
+/// Number of the last 8 byte register, `rip`.
+const RIP: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    /// The byte replaced by `int3` while the breakpoint is inserted.
+    original: u8,
+}
+
+struct Breakpoints {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// Whether the breakpoints are currently written into memory.
+    inserted: bool,
+}
+
+impl Breakpoints {
+    fn contains(&self, addr: usize) -> bool {
+        self.breakpoints.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    /// Add a breakpoint at `addr`, returning `false` if there's no room for it.
+    fn add(&mut self, addr: usize) -> bool {
+        if self.contains(addr) {
+            return true;
+        }
+        match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => {
+                *slot = Some(Breakpoint { addr, original: 0 });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, addr: usize) {
+        for slot in self.breakpoints.iter_mut() {
+            if slot.is_some_and(|bp| bp.addr == addr) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.breakpoints = [None; MAX_BREAKPOINTS];
+    }
+
+    /// Write `int3` over each breakpoint, saving the bytes it replaces.
+    fn insert(&mut self) {
+        for bp in self.breakpoints.iter_mut().flatten() {
+            if is_accessible(bp.addr, 1) {
+                // Safety: The byte was just checked to be mapped
+                unsafe {
+                    bp.original = ptr::read_volatile(bp.addr as *const u8);
+                    write_memory(bp.addr, &[INT3]);
+                }
+            }
+        }
+        self.inserted = true;
+    }
+
+    /// Put back the bytes replaced by [`insert`](Self::insert).
+    fn restore(&mut self) {
+        if !self.inserted {
+            return;
+        }
+        for bp in self.breakpoints.iter().flatten() {
+            if is_accessible(bp.addr, 1) {
+                // Safety: The byte was just checked to be mapped, and holds our `int3`
+                unsafe { write_memory(bp.addr, &[bp.original]) };
+            }
+        }
+        self.inserted = false;
+    }
+}
+
+struct Stub {
+    breakpoints: Breakpoints,
+    /// Whether GDB told the kernel to resume, so is waiting to hear when it stops.
+    resumed: bool,
+    /// Whether the kernel is single-stepping over a breakpoint to continue from it, so should
+    /// insert the breakpoints and carry on instead of stopping after the step.
+    stepping_over: bool,
+    packet: Packet,
+    reply: Packet,
+}
+
+static STUB: Mutex<Stub> = Mutex::new(Stub {
+    breakpoints: Breakpoints {
+        breakpoints: [None; MAX_BREAKPOINTS],
+        inserted: false,
+    },
+    resumed: false,
+    stepping_over: false,
+    packet: Packet::new(),
+    reply: Packet::new(),
+});
+
+/// The registers of the stopped code.
+struct Cpu<'a> {
+    general: &'a mut GeneralRegisters,
+    frame: &'a mut InterruptStackFrame,
+}
+
+impl Cpu<'_> {
+    fn update_frame(&mut self, f: impl FnOnce(&mut InterruptStackFrameValue)) {
+        // Safety: Only the stopped code's registers are changed, as asked for by GDB
+        unsafe { self.frame.as_mut().update(f) };
+    }
+
+    fn rip(&self) -> usize {
+        self.frame.instruction_pointer.as_u64() as usize
+    }
+
+    /// Set RIP, returning `false` if `rip` isn't canonical.
+    fn set_rip(&mut self, rip: u64) -> bool {
+        match VirtAddr::try_new(rip) {
+            Ok(rip) => {
+                self.update_frame(|frame| frame.instruction_pointer = rip);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn set_trap_flag(&mut self, set: bool) {
+        self.update_frame(|frame| {
+            if set {
+                frame.cpu_flags |= TRAP_FLAG;
+            } else {
+                frame.cpu_flags &= !TRAP_FLAG;
+            }
+        });
+    }
+
+    /// Returns register number `n`, in GDB's numbering.
+    fn register(&self, n: usize) -> Option<u64> {
+        let general = &*self.general;
+        Some(match n {
+            0 => general.rax,
+            1 => general.rbx,
+            2 => general.rcx,
+            3 => general.rdx,
+            4 => general.rsi,
+            5 => general.rdi,
+            6 => general.rbp,
+            7 => self.frame.stack_pointer.as_u64(),
+            8 => general.r8,
+            9 => general.r9,
+            10 => general.r10,
+            11 => general.r11,
+            12 => general.r12,
+            13 => general.r13,
+            14 => general.r14,
+            15 => general.r15,
+            RIP => self.frame.instruction_pointer.as_u64(),
+            17 => self.frame.cpu_flags,
+            18 => self.frame.code_segment,
+            19 => self.frame.stack_segment,
+            // The handlers don't change the data segments, so they're still the stopped code's
+            20 => DS::get_reg().0 as u64,
+            21 => ES::get_reg().0 as u64,
+            22 => FS::get_reg().0 as u64,
+            23 => GS::get_reg().0 as u64,
+            _ => return None,
+        })
+    }
+
+    /// Set register number `n`, in GDB's numbering, returning `false` if it can't be set to
+    /// `value`. Changes to segment registers are ignored.
+    fn set_register(&mut self, n: usize, value: u64) -> bool {
+        let general = &mut *self.general;
+        match n {
+            0 => general.rax = value,
+            1 => general.rbx = value,
+            2 => general.rcx = value,
+            3 => general.rdx = value,
+            4 => general.rsi = value,
+            5 => general.rdi = value,
+            6 => general.rbp = value,
+            7 => match VirtAddr::try_new(value) {
+                Ok(rsp) => self.update_frame(|frame| frame.stack_pointer = rsp),
+                Err(_) => return false,
+            },
+            8 => general.r8 = value,
+            9 => general.r9 = value,
+            10 => general.r10 = value,
+            11 => general.r11 = value,
+            12 => general.r12 = value,
+            13 => general.r13 = value,
+            14 => general.r14 = value,
+            15 => general.r15 = value,
+            RIP => return self.set_rip(value),
+            17 => self.update_frame(|frame| frame.cpu_flags = value),
+            18..=23 => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Size of register number `n` in packets, in bytes.
+fn register_size(n: usize) -> usize {
+    if n <= RIP {
+        8
+    } else {
+        4
+    }
+}
+
+/// Returns whether the `len` bytes starting at `addr` are all mapped.
+fn is_accessible(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => (addr & !(Size4K::SIZE - 1)..end)
+            .step_by(Size4K::SIZE)
+            .all(|page| memory::VirtAddr::try_new(page).is_some_and(paging::is_mapped)),
+        None => false,
+    }
+}
+
+/// Write `bytes` to `addr`, even if it's mapped read-only (E.G. to set a breakpoint in the
+/// kernel's code).
+///
+/// # Safety
+///
+/// The memory must be mapped, and safe for the stopped code to have changed underneath it.
+unsafe fn write_memory(addr: usize, bytes: &[u8]) {
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+    for (i, &byte) in bytes.iter().enumerate() {
+        ptr::write_volatile((addr + i) as *mut u8, byte);
+    }
+    Cr0::write(cr0);
+}
+
+/// Split `args` at the first `separator`.
+fn split(args: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let i = args.iter().position(|&byte| byte == separator)?;
+    Some((&args[..i], &args[i + 1..]))
+}
+
+/// Parse an `addr,len` pair.
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let (addr, len) = split(args, b',')?;
+    Some((parse_hex(addr)? as usize, parse_hex(len)? as usize))
+}
+
+/// What to do once a packet has been handled.
+enum Action {
+    /// Send the reply, and wait for the next packet.
+    Reply,
+    Continue,
+    Step,
+    /// Remove the breakpoints, send the reply (if any) and resume.
+    Detach,
+}
+
+/// Handle a packet from GDB, writing the reply (if any) into `reply`. An empty reply tells GDB
+/// the command isn't supported.
+fn process(
+    cpu: &mut Cpu,
+    breakpoints: &mut Breakpoints,
+    packet: &[u8],
+    reply: &mut Packet,
+) -> Action {
+    let (&command, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return Action::Reply,
+    };
+    let result = match command {
+        b'?' => write!(reply, "S{:02x}", SIGTRAP).ok(),
+        b'g' => {
+            for n in 0..REGISTER_COUNT {
+                let value = cpu.register(n).unwrap_or(0);
+                reply.push_hex(&value.to_le_bytes()[..register_size(n)]);
+            }
+            Some(())
+        }
+        b'G' => (|| {
+            let mut digits = args;
+            for n in 0..REGISTER_COUNT {
+                let size = register_size(n);
+                let mut value = [0; 8];
+                decode_hex(digits.get(..size * 2)?, &mut value[..size])?;
+                cpu.set_register(n, u64::from_le_bytes(value))
+                    .then_some(())?;
+                digits = &digits[size * 2..];
+            }
+            reply.write_str("OK").ok()
+        })(),
+        b'p' => (|| {
+            let n = parse_hex(args)? as usize;
+            let value = cpu.register(n)?;
+            reply.push_hex(&value.to_le_bytes()[..register_size(n)]);
+            Some(())
+        })(),
+        b'P' => (|| {
+            let (n, digits) = split(args, b'=')?;
+            let n = parse_hex(n)? as usize;
+            let mut value = [0; 8];
+            decode_hex(digits, value.get_mut(..register_size(n))?)?;
+            cpu.set_register(n, u64::from_le_bytes(value))
+                .then_some(())?;
+            reply.write_str("OK").ok()
+        })(),
+        b'm' => (|| {
+            let (addr, len) = parse_range(args)?;
+            let len = len.min(PACKET_SIZE / 2);
+            if !is_accessible(addr, len) {
+                return reply.write_str("E14").ok();
+            }
+            for i in 0..len {
+                // Safety: The range was just checked to be mapped
+                reply.push_hex(&[unsafe { ptr::read_volatile((addr + i) as *const u8) }]);
+            }
+            Some(())
+        })(),
+        b'M' => (|| {
+            let (range, digits) = split(args, b':')?;
+            let (addr, len) = parse_range(range)?;
+            let mut bytes = [0; PACKET_SIZE / 2];
+            decode_hex(digits, bytes.get_mut(..len)?)?;
+            if !is_accessible(addr, len) {
+                return reply.write_str("E14").ok();
+            }
+            // Safety: The range was just checked to be mapped, and GDB asked for the write
+            unsafe { write_memory(addr, &bytes[..len]) };
+            reply.write_str("OK").ok()
+        })(),
+        // Only software breakpoints (type 0) are supported
+        b'Z' | b'z' if !args.starts_with(b"0,") => Some(()),
+        b'Z' | b'z' => (|| {
+            let (addr, _kind) = parse_range(&args[2..])?;
+            if command == b'z' {
+                breakpoints.remove(addr);
+            } else if !is_accessible(addr, 1) {
+                return reply.write_str("E14").ok();
+            } else if !breakpoints.add(addr) {
+                return reply.write_str("E28").ok();
+            }
+            reply.write_str("OK").ok()
+        })(),
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                cpu.set_rip(addr);
+            }
+            return if command == b'c' {
+                Action::Continue
+            } else {
+                Action::Step
+            };
+        }
+        b'D' => {
+            let _ = reply.write_str("OK");
+            return Action::Detach;
+        }
+        // There's nothing to kill, so just let the kernel carry on
+        b'k' => return Action::Detach,
+        b'q' if args.starts_with(b"Supported") => {
+            write!(reply, "PacketSize={:x}", PACKET_SIZE).ok()
+        }
+        b'q' if args == b"Attached" => reply.write_str("1").ok(),
+        // There's only one thread
+        b'H' => reply.write_str("OK").ok(),
+        _ => Some(()),
+    };
+    if result.is_none() {
+        reply.clear();
+        let _ = reply.write_str("E01");
+    }
+    Action::Reply
+}
+
+/// The override for the breakpoint and debug exceptions, which reports the stop to GDB and
+/// handles its commands until it resumes the kernel.
+fn handle_exception(context: &mut ExceptionContext) -> bool {
+    let exception = context.exception;
+    let mut cpu = Cpu {
+        general: &mut *context.general,
+        frame: context.frame,
+    };
+    let mut stub = STUB.lock();
+    let stub = &mut *stub;
+
+    match exception {
+        Exception::Breakpoint => {
+            // RIP is after the `int3`, so move it back to the instruction it replaced
+            let addr = cpu.rip() - 1;
+            if stub.breakpoints.inserted && stub.breakpoints.contains(addr) {
+                cpu.set_rip(addr as u64);
+            }
+        }
+        Exception::Debug => {
+            cpu.set_trap_flag(false);
+            if stub.stepping_over {
+                stub.stepping_over = false;
+                stub.breakpoints.insert();
+                return true;
+            }
+        }
+        _ => return false,
+    }
+    stub.breakpoints.restore();
+
+    if stub.resumed {
+        stub.resumed = false;
+        stub.reply.clear();
+        let _ = write!(stub.reply, "S{:02x}", SIGTRAP);
+        stub.reply.send(&COM2);
+    }
+
+    loop {
+        stub.packet.receive(&COM2);
+        stub.reply.clear();
+        match process(
+            &mut cpu,
+            &mut stub.breakpoints,
+            stub.packet.data(),
+            &mut stub.reply,
+        ) {
+            Action::Reply => stub.reply.send(&COM2),
+            Action::Continue => {
+                stub.resumed = true;
+                // Step over the instruction first if it has a breakpoint, so we don't stop
+                // straight away
+                if stub.breakpoints.contains(cpu.rip()) {
+                    stub.stepping_over = true;
+                    cpu.set_trap_flag(true);
+                } else {
+                    stub.breakpoints.insert();
+                }
+                return true;
+            }
+            Action::Step => {
+                stub.resumed = true;
+                cpu.set_trap_flag(true);
+                return true;
+            }
+            Action::Detach => {
+                if !stub.reply.data().is_empty() {
+                    stub.reply.send(&COM2);
+                }
+                stub.breakpoints.clear();
+                return true;
+            }
+        }
+    }
+}
+
+/// Take over the breakpoint and debug exceptions, and add the `gdb` shell command, if the second
+/// serial port is present. If the kernel command line `cmdline` has the `gdb` option, stop until
+/// GDB attaches.
+pub fn init(cmdline: &str) {
+    if !COM2.is_present() {
+        return;
+    }
+    set_exception_handler(Exception::Breakpoint, handle_exception);
+    set_exception_handler(Exception::Debug, handle_exception);
+    crate::shell::register_command("gdb", "Stop the kernel until GDB attaches", gdb_command);
+    info!("GDB stub listening on COM2");
+
+    if cmdline.split_whitespace().any(|option| option == "gdb") {
+        wait_for_gdb();
+    }
+}
+
+/// Stop in the stub, until GDB attaches and resumes the kernel.
+fn wait_for_gdb() {
+    info!("Waiting for GDB to attach");
+    x86_64::instructions::interrupts::int3();
+}
+
+fn gdb_command(_args: &[&str]) {
+    wait_for_gdb();
+}
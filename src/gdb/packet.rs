@@ -0,0 +1,159 @@
+//! Framing of GDB remote serial protocol packets, E.G. `$m1000,4#8c`, and the hex encoding used
+//! inside them.
+
+use core::fmt;
+
+use crate::output::serial::SerialDevice;
+
+/// Maximum length of a packet's data. GDB is told this in the `qSupported` reply, so it never
+/// sends anything longer.
+pub const PACKET_SIZE: usize = 1024;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// The data of a packet, without the `$`, `#` and checksum around it.
+pub struct Packet {
+    buffer: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Packet {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Append `byte`, dropping it if the packet is full.
+    pub fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Append `bytes`, as two hex digits each.
+    pub fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(HEX_DIGITS[(byte >> 4) as usize]);
+            self.push(HEX_DIGITS[(byte & 0xf) as usize]);
+        }
+    }
+
+    /// Wait for a packet from `port`, replacing the contents of this one. Packets with a bad
+    /// checksum are asked for again, and anything outside a packet (E.G. stray acknowledgements)
+    /// is ignored.
+    pub fn receive(&mut self, port: &SerialDevice) {
+        loop {
+            while wait_byte(port) != b'$' {}
+
+            self.len = 0;
+            let mut overflowed = false;
+            let mut checksum = 0u8;
+            loop {
+                let byte = wait_byte(port);
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                overflowed |= self.len == PACKET_SIZE;
+                self.push(byte);
+            }
+
+            let expected = decode_byte(wait_byte(port), wait_byte(port));
+            if !overflowed && expected == Some(checksum) {
+                port.write_byte(b'+');
+                return;
+            }
+            port.write_byte(b'-');
+        }
+    }
+
+    /// Send this packet to `port`, resending it until GDB acknowledges it.
+    pub fn send(&self, port: &SerialDevice) {
+        let checksum = self
+            .data()
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        loop {
+            port.write_byte(b'$');
+            for &byte in self.data() {
+                port.write_byte(byte);
+            }
+            port.write_byte(b'#');
+            port.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+            port.write_byte(HEX_DIGITS[(checksum & 0xf) as usize]);
+
+            loop {
+                match wait_byte(port) {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Write for Packet {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > PACKET_SIZE - self.len {
+            return Err(fmt::Error);
+        }
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Spin until a byte is received on `port`. Interrupts are disabled while the stub runs, so this
+/// polls the port.
+fn wait_byte(port: &SerialDevice) -> u8 {
+    loop {
+        if let Some(byte) = port.poll_byte() {
+            return byte;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the value of the hex digit `digit`.
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Returns the byte encoded by the hex digits `high` and `low`.
+fn decode_byte(high: u8, low: u8) -> Option<u8> {
+    Some(hex_value(high)? << 4 | hex_value(low)?)
+}
+
+/// Parse a big endian hex number, as used for addresses and lengths.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0, |value, &digit| {
+        Some(value << 4 | hex_value(digit)? as u64)
+    })
+}
+
+/// Decode pairs of hex digits from `digits` into `out`, which must be exactly the right length.
+pub fn decode_hex(digits: &[u8], out: &mut [u8]) -> Option<()> {
+    if digits.len() != out.len() * 2 {
+        return None;
+    }
+    for (byte, &[high, low]) in out.iter_mut().zip(digits.as_chunks::<2>().0) {
+        *byte = decode_byte(high, low)?;
+    }
+    Some(())
+}
@@ -13,7 +13,7 @@ struct GdtWrapper {
 static GDT: Lazy<GdtWrapper> = Lazy::new(|| {
     let mut gdt = GlobalDescriptorTable::new();
     let code_sel = gdt.add_entry(Descriptor::kernel_code_segment());
-    let tss_sel = gdt.add_entry(Descriptor::tss_segment(&tss::TSS));
+    let tss_sel = gdt.add_entry(Descriptor::tss_segment(tss::init()));
     GdtWrapper {
         gdt,
         code_sel,
@@ -1,20 +1,102 @@
-use spin::Lazy;
-use x86_64::{structures::tss::TaskStateSegment, VirtAddr};
+use core::ptr::{addr_of, addr_of_mut};
+
+use x86_64::structures::tss::TaskStateSegment;
+
+use crate::memory::{
+    paging::{ActivePageTable, EntryFlags, Page},
+    phys::FrameAllocator,
+    Size4K, SizedRegion, VirtAddr,
+};
 
 /// Index of the stack used when a double-fault occurs.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 6;
+/// Index of the stack used when a non-maskable interrupt occurs.
+pub const NMI_IST_INDEX: u16 = 5;
+/// Index of the stack used when a machine check occurs.
+pub const MACHINE_CHECK_IST_INDEX: u16 = 4;
 
 const STACK_SIZE: usize = 4096 * 5;
 
-/// Storage space for the double-fault stack.
-/// Todo: Replace this with allocated memory with a propper guard page.
-static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-pub static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
-    let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-        let stack_start = VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK });
-        stack_start + STACK_SIZE
-    };
-    tss
-});
+/// Start of the virtual address range reserved for the IST stacks (the first address mapped by
+/// the fourth P4 entry).
+const IST_STACKS_START: usize = 0x0000_0180_0000_0000;
+
+/// The IST stacks, and the names used to report overflowing them. Each one gets a slot of
+/// [`STACK_SIZE`] bytes, with an unmapped guard page below it.
+const IST_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault"),
+    (NMI_IST_INDEX, "NMI"),
+    (MACHINE_CHECK_IST_INDEX, "machine check"),
+];
+
+/// Returns the address of the guard page of the IST stack in slot `slot`. The stack itself
+/// starts at the end of the guard page.
+const fn guard_page(slot: usize) -> usize {
+    IST_STACKS_START + slot * (Size4K::SIZE + STACK_SIZE)
+}
+
+/// Stacks used by the IST entries until [`map_ist_stacks`] has run, so that a double fault, NMI
+/// or machine check during early boot doesn't triple fault. Unlike the real stacks, they have no
+/// guard pages.
+#[repr(align(16))]
+struct BootstrapStacks([[u8; STACK_SIZE]; IST_STACKS.len()]);
+
+static mut BOOTSTRAP_STACKS: BootstrapStacks = BootstrapStacks([[0; STACK_SIZE]; IST_STACKS.len()]);
+
+/// The TSS. Its IST entries point at [`BOOTSTRAP_STACKS`] from [`init`] until [`map_ist_stacks`]
+/// repoints them at the mapped stacks.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Point the IST entries at the bootstrap stacks, and return the TSS for the GDT to describe.
+pub fn init() -> &'static TaskStateSegment {
+    for (slot, &(index, _)) in IST_STACKS.iter().enumerate() {
+        // Safety: The bootstrap stacks are only ever used by the CPU through the TSS
+        let stack = unsafe { addr_of!(BOOTSTRAP_STACKS.0[slot]) };
+        set_stack(index, stack as usize + STACK_SIZE);
+    }
+    // Safety: The reference is only used for the address of the TSS, which is all a GDT
+    // descriptor stores, so it doesn't outlive the writes made by `set_stack`
+    unsafe { &*addr_of!(TSS) }
+}
+
+/// Set IST entry `index` to the stack ending at `stack_end`.
+fn set_stack(index: u16, stack_end: usize) {
+    // Safety: Apart from here, the TSS is only accessed by the CPU, when it switches to an IST
+    // stack
+    unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[index as usize] =
+            x86_64::VirtAddr::new(stack_end as u64);
+    }
+}
+
+/// Map the IST stacks to newly allocated frames, leaving their guard pages unmapped, and point
+/// the IST entries at them instead of the bootstrap stacks.
+pub fn map_ist_stacks<A>(active_table: &mut ActivePageTable, allocator: &mut A)
+where
+    A: FrameAllocator<Size4K>,
+{
+    for (slot, &(index, _)) in IST_STACKS.iter().enumerate() {
+        let stack_start = guard_page(slot) + Size4K::SIZE;
+        let mut addr = VirtAddr::new(stack_start);
+        while addr < VirtAddr::new(stack_start + STACK_SIZE) {
+            active_table.map(
+                Page::<Size4K>::containing_addr(addr),
+                EntryFlags::WRITABLE | EntryFlags::NO_EXEC,
+                allocator,
+            );
+            addr += Size4K::SIZE;
+        }
+        set_stack(index, stack_start + STACK_SIZE);
+    }
+}
+
+/// If `addr` lies in the guard page of one of the IST stacks, returns the name of that stack.
+pub fn ist_guard_page(addr: VirtAddr) -> Option<&'static str> {
+    IST_STACKS
+        .iter()
+        .enumerate()
+        .find(|&(slot, _)| {
+            (guard_page(slot)..guard_page(slot) + Size4K::SIZE).contains(&addr.as_usize())
+        })
+        .map(|(_, &(_, name))| name)
+}
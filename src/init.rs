@@ -1,8 +1,34 @@
-use crate::{memory::phys::SimpleFrameAllocator, println};
+use log::{info, log_enabled, Level};
+use spin::Once;
+
+use crate::println;
 
 const KERNEL_NAME: &str = env!("CARGO_PKG_NAME");
 const KERNEL_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The multiboot information structure passed to us by the bootloader.
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+struct BootInfo(multiboot2::BootInformation);
+
+// Safety: The multiboot information is never modified, and the memory it lives in is reserved
+// (and mapped) for the lifetime of the kernel.
+unsafe impl Send for BootInfo {}
+unsafe impl Sync for BootInfo {}
+
+/// Returns the multiboot information structure passed to us by the bootloader.
+///
+/// # Panics
+///
+/// * If called before the multiboot information has been loaded in [`kernel_main`].
+#[allow(dead_code)]
+pub fn boot_info() -> &'static multiboot2::BootInformation {
+    &BOOT_INFO
+        .get()
+        .expect("Multiboot information not loaded yet")
+        .0
+}
+
 #[no_mangle]
 pub extern "C" fn kernel_main(multiboot_info: usize) -> ! {
     crate::gdt::init();
@@ -10,36 +36,61 @@ pub extern "C" fn kernel_main(multiboot_info: usize) -> ! {
 
     let multiboot_info =
         unsafe { multiboot2::load(multiboot_info).expect("Invalid Multiboot 2 information") };
+    let multiboot_info = &BOOT_INFO.call_once(|| BootInfo(multiboot_info)).0;
+
+    let cmdline = multiboot_info
+        .command_line_tag()
+        .map(|t| t.command_line())
+        .unwrap_or("");
+    crate::logger::init(cmdline);
 
     let bootloader = multiboot_info
         .boot_loader_name_tag()
         .map(|t| t.name())
         .unwrap_or("unknown bootloader");
 
+    crate::time::rtc::init();
+    let now = crate::time::rtc::wall_clock();
+
     println!(
-        "Starting {} kernel v.{}, booted by {:?}",
-        KERNEL_NAME, KERNEL_VERSION, bootloader
+        "Starting {} kernel v.{}, booted by {:?} at {} (UNIX time {})",
+        KERNEL_NAME, KERNEL_VERSION, bootloader, now.date, now.timestamp
     );
 
-    let cmdline = multiboot_info
-        .command_line_tag()
-        .map(|t| t.command_line())
-        .unwrap_or("");
+    info!("Kernel cmdline: {:?}", cmdline);
 
-    println!("Kernel cmdline: {:?}", cmdline);
+    // These are also available from the shell, so are only shown at boot when debugging
+    if log_enabled!(Level::Debug) {
+        print_memory_areas(multiboot_info);
+        print_elf_sections(multiboot_info);
+    }
 
-    print_memory_areas(&multiboot_info);
-    print_elf_sections(&multiboot_info);
+    crate::memory::init(multiboot_info);
+    crate::backtrace::init(multiboot_info);
+    info!("Kernel remapped");
 
-    let fa = SimpleFrameAllocator::new(&multiboot_info);
-    for frame in fa.skip(159).take(10) {
-        println!("{:?}", frame);
-    }
+    // Subsystems can add their own shell commands from here on
+    crate::shell::init();
+    crate::dmesg::init();
+
+    crate::acpi::init(multiboot_info);
+    crate::time::rtc::init_century();
+    crate::interrupts::apic::init();
+    crate::time::init(crate::time::DEFAULT_FREQUENCY);
+    info!("Timer ticking at {} Hz", crate::time::frequency());
+    crate::time::clock::init();
+    crate::input::init();
+    crate::output::serial::init();
+    crate::gdb::init(cmdline);
+
+    #[cfg(test)]
+    crate::test_main();
 
-    crate::hlt_loop();
+    crate::shell::run();
 }
 
-fn print_memory_areas(mb: &multiboot2::BootInformation) {
+/// Print the memory areas in the multiboot memory map.
+pub fn print_memory_areas(mb: &multiboot2::BootInformation) {
     let mm_tag = mb
         .memory_map_tag()
         .expect("Multiboot2 structure must have a memory map tag");
@@ -59,7 +110,8 @@ fn print_memory_areas(mb: &multiboot2::BootInformation) {
     }
 }
 
-fn print_elf_sections(mb: &multiboot2::BootInformation) {
+/// Print the kernel's ELF sections.
+pub fn print_elf_sections(mb: &multiboot2::BootInformation) {
     let elf_tag = mb
         .elf_sections_tag()
         .expect("Multiboot2 structure must have an ELF sections tag");
@@ -0,0 +1,783 @@
+//! PS/2 keyboard driver, decoding scancodes (set 1 or 2) into key events and characters.
+
+use bitflags::bitflags;
+use log::{info, warn};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{
+    keymap::{self, Keymap},
+    ps2::{self, Ps2Port},
+    InputEvent,
+};
+use crate::{interrupts::irq, ring_buffer::RingBuffer};
+
+/// IRQ raised by the keyboard.
+const KEYBOARD_IRQ: u8 = 1;
+
+const CMD_ENABLE_SCANNING: u8 = 0xf4;
+
+/// A physical key, named after its meaning on a US keyboard.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    Backquote,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    /// The key above enter, which is `#~` on ISO (E.G. UK) keyboards.
+    Backslash,
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+    LeftShift,
+    /// The extra key next to left shift on ISO (E.G. UK) keyboards.
+    NonUsBackslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftCtrl,
+    LeftGui,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightGui,
+    Menu,
+    RightCtrl,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    Up,
+    Left,
+    Down,
+    Right,
+    NumLock,
+    KeypadDivide,
+    KeypadMultiply,
+    KeypadMinus,
+    KeypadPlus,
+    KeypadEnter,
+    KeypadPeriod,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+}
+
+impl KeyCode {
+    /// The lowercase letter on this key, if it is a letter key.
+    pub fn letter(self) -> Option<char> {
+        use KeyCode::*;
+        const LETTERS: [KeyCode; 26] = [
+            A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        ];
+        LETTERS
+            .iter()
+            .position(|&letter| letter == self)
+            .map(|i| (b'a' + i as u8) as char)
+    }
+
+    /// The character produced by this key if it is on the keypad, which depends on whether num
+    /// lock is on.
+    pub fn keypad_char(self, num_lock: bool) -> Option<char> {
+        use KeyCode::*;
+        match self {
+            KeypadDivide => Some('/'),
+            KeypadMultiply => Some('*'),
+            KeypadMinus => Some('-'),
+            KeypadPlus => Some('+'),
+            _ if !num_lock => None,
+            KeypadPeriod => Some('.'),
+            Keypad0 => Some('0'),
+            Keypad1 => Some('1'),
+            Keypad2 => Some('2'),
+            Keypad3 => Some('3'),
+            Keypad4 => Some('4'),
+            Keypad5 => Some('5'),
+            Keypad6 => Some('6'),
+            Keypad7 => Some('7'),
+            Keypad8 => Some('8'),
+            Keypad9 => Some('9'),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// The modifier keys held, and lock keys toggled on.
+    pub struct Modifiers: u16 {
+        const LEFT_SHIFT = 1 << 0;
+        const RIGHT_SHIFT = 1 << 1;
+        const LEFT_CTRL = 1 << 2;
+        const RIGHT_CTRL = 1 << 3;
+        const LEFT_ALT = 1 << 4;
+        /// AltGr on non-US layouts.
+        const RIGHT_ALT = 1 << 5;
+        const CAPS_LOCK = 1 << 6;
+        const NUM_LOCK = 1 << 7;
+        const SCROLL_LOCK = 1 << 8;
+
+        const SHIFT = Self::LEFT_SHIFT.bits | Self::RIGHT_SHIFT.bits;
+        const CTRL = Self::LEFT_CTRL.bits | Self::RIGHT_CTRL.bits;
+        const ALT = Self::LEFT_ALT.bits | Self::RIGHT_ALT.bits;
+    }
+}
+
+impl Modifiers {
+    /// The modifier held while `key` is held, or the lock toggled when it is pressed.
+    fn for_key(key: KeyCode) -> Self {
+        match key {
+            KeyCode::LeftShift => Self::LEFT_SHIFT,
+            KeyCode::RightShift => Self::RIGHT_SHIFT,
+            KeyCode::LeftCtrl => Self::LEFT_CTRL,
+            KeyCode::RightCtrl => Self::RIGHT_CTRL,
+            KeyCode::LeftAlt => Self::LEFT_ALT,
+            KeyCode::RightAlt => Self::RIGHT_ALT,
+            KeyCode::CapsLock => Self::CAPS_LOCK,
+            KeyCode::NumLock => Self::NUM_LOCK,
+            KeyCode::ScrollLock => Self::SCROLL_LOCK,
+            _ => Self::empty(),
+        }
+    }
+
+    const LOCKS: Self = Self::from_bits_truncate(
+        Self::CAPS_LOCK.bits | Self::NUM_LOCK.bits | Self::SCROLL_LOCK.bits,
+    );
+}
+
+/// A key being pressed or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: KeyCode,
+    pub pressed: bool,
+    /// The modifiers in effect after this event.
+    pub modifiers: Modifiers,
+}
+
+/// The scancode sets that can be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+/// Decodes a stream of scancode bytes into key presses and releases.
+pub struct Decoder {
+    set: ScancodeSet,
+    /// The last byte was the 0xe0 prefix of an extended key.
+    extended: bool,
+    /// The last byte was the 0xf0 prefix of a release (set 2 only).
+    release: bool,
+    /// Number of bytes left of a pause key sequence, which are ignored.
+    pause_remaining: u8,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            extended: false,
+            release: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// Decode the next byte, returning the key and whether it was pressed once a whole scancode
+    /// has been received.
+    pub fn feed(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+            return None;
+        }
+
+        match (self.set, byte) {
+            (_, 0xe0) => {
+                self.extended = true;
+                return None;
+            }
+            // Pause sends its whole press and release sequence at once, starting with 0xe1
+            (ScancodeSet::Set1, 0xe1) => {
+                self.pause_remaining = 5;
+                return Some((KeyCode::Pause, true));
+            }
+            (ScancodeSet::Set2, 0xe1) => {
+                self.pause_remaining = 7;
+                return Some((KeyCode::Pause, true));
+            }
+            (ScancodeSet::Set2, 0xf0) => {
+                self.release = true;
+                return None;
+            }
+            _ => {}
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => (byte & 0x7f, byte & 0x80 == 0),
+            ScancodeSet::Set2 => (byte, !core::mem::take(&mut self.release)),
+        };
+        match (self.set, extended) {
+            (ScancodeSet::Set1, false) => set1_key(code),
+            (ScancodeSet::Set1, true) => set1_extended_key(code),
+            (ScancodeSet::Set2, false) => set2_key(code),
+            (ScancodeSet::Set2, true) => set2_extended_key(code),
+        }
+        .map(|key| (key, pressed))
+    }
+}
+
+/// Decode a scancode from set 1, without the release bit.
+fn set1_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0a => Key9,
+        0x0b => Key0,
+        0x0c => Minus,
+        0x0d => Equals,
+        0x0e => Backspace,
+        0x0f => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1a => LeftBracket,
+        0x1b => RightBracket,
+        0x1c => Enter,
+        0x1d => LeftCtrl,
+        0x1e => A,
+        0x1f => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Backquote,
+        0x2a => LeftShift,
+        0x2b => Backslash,
+        0x2c => Z,
+        0x2d => X,
+        0x2e => C,
+        0x2f => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KeypadMultiply,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3a => CapsLock,
+        0x3b => F1,
+        0x3c => F2,
+        0x3d => F3,
+        0x3e => F4,
+        0x3f => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Keypad7,
+        0x48 => Keypad8,
+        0x49 => Keypad9,
+        0x4a => KeypadMinus,
+        0x4b => Keypad4,
+        0x4c => Keypad5,
+        0x4d => Keypad6,
+        0x4e => KeypadPlus,
+        0x4f => Keypad1,
+        0x50 => Keypad2,
+        0x51 => Keypad3,
+        0x52 => Keypad0,
+        0x53 => KeypadPeriod,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+/// Decode an extended (0xe0 prefixed) scancode from set 1, without the release bit.
+fn set1_extended_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x1c => KeypadEnter,
+        0x1d => RightCtrl,
+        0x35 => KeypadDivide,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4b => Left,
+        0x4d => Right,
+        0x4f => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5b => LeftGui,
+        0x5c => RightGui,
+        0x5d => Menu,
+        // Including the fake shifts sent around print screen and the navigation keys
+        _ => return None,
+    })
+}
+
+/// Decode a scancode from set 2.
+fn set2_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0a => F8,
+        0x0b => F6,
+        0x0c => F4,
+        0x0d => Tab,
+        0x0e => Backquote,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x14 => LeftCtrl,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1a => Z,
+        0x1b => S,
+        0x1c => A,
+        0x1d => W,
+        0x1e => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Space,
+        0x2a => V,
+        0x2b => F,
+        0x2c => T,
+        0x2d => R,
+        0x2e => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3a => M,
+        0x3b => J,
+        0x3c => U,
+        0x3d => Key7,
+        0x3e => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Period,
+        0x4a => Slash,
+        0x4b => L,
+        0x4c => Semicolon,
+        0x4d => P,
+        0x4e => Minus,
+        0x52 => Quote,
+        0x54 => LeftBracket,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5a => Enter,
+        0x5b => RightBracket,
+        0x5d => Backslash,
+        0x61 => NonUsBackslash,
+        0x66 => Backspace,
+        0x69 => Keypad1,
+        0x6b => Keypad4,
+        0x6c => Keypad7,
+        0x70 => Keypad0,
+        0x71 => KeypadPeriod,
+        0x72 => Keypad2,
+        0x73 => Keypad5,
+        0x74 => Keypad6,
+        0x75 => Keypad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KeypadPlus,
+        0x7a => Keypad3,
+        0x7b => KeypadMinus,
+        0x7c => KeypadMultiply,
+        0x7d => Keypad9,
+        0x7e => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+/// Decode an extended (0xe0 prefixed) scancode from set 2.
+fn set2_extended_key(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x11 => RightAlt,
+        0x14 => RightCtrl,
+        0x1f => LeftGui,
+        0x27 => RightGui,
+        0x2f => Menu,
+        0x4a => KeypadDivide,
+        0x5a => KeypadEnter,
+        0x69 => End,
+        0x6b => Left,
+        0x6c => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => Down,
+        0x74 => Right,
+        0x75 => Up,
+        0x7a => PageDown,
+        0x7c => PrintScreen,
+        0x7d => PageUp,
+        // Including the fake shifts sent around print screen and the navigation keys
+        _ => return None,
+    })
+}
+
+/// State of the keyboard driver, only touched by the IRQ handler once set up.
+struct Keyboard {
+    decoder: Decoder,
+    modifiers: Modifiers,
+    keymap: &'static Keymap,
+}
+
+impl Keyboard {
+    const fn new(set: ScancodeSet, keymap: &'static Keymap) -> Self {
+        Self {
+            decoder: Decoder::new(set),
+            modifiers: Modifiers::empty(),
+            keymap,
+        }
+    }
+
+    /// Decode the next byte from the keyboard, returning the key event and the character typed
+    /// (if any) once a whole scancode has been received.
+    fn feed(&mut self, byte: u8) -> Option<(KeyEvent, Option<char>)> {
+        let (key, pressed) = self.decoder.feed(byte)?;
+
+        let modifier = Modifiers::for_key(key);
+        if Modifiers::LOCKS.contains(modifier) {
+            if pressed {
+                self.modifiers.toggle(modifier);
+            }
+        } else {
+            self.modifiers.set(modifier, pressed);
+        }
+
+        let event = KeyEvent {
+            key,
+            pressed,
+            modifiers: self.modifiers,
+        };
+        let c = if pressed {
+            self.keymap.translate(key, self.modifiers)
+        } else {
+            None
+        };
+        Some((event, c))
+    }
+}
+
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(ScancodeSet::Set1, &keymap::US));
+
+/// Characters typed, waiting to be read.
+static CHARS: RingBuffer<char, 256> = RingBuffer::new();
+
+/// Set up the keyboard on the first PS/2 port, and register its IRQ handler. Returns whether the
+/// keyboard is ready for its IRQ to be enabled in the controller.
+pub fn init(controller: &ps2::Controller) -> bool {
+    if !controller.first_port {
+        info!("No PS/2 keyboard found");
+        return false;
+    }
+
+    // Without translation, keyboards use set 2 by default
+    let set = if controller.translation {
+        ScancodeSet::Set1
+    } else {
+        ScancodeSet::Set2
+    };
+    KEYBOARD.lock().decoder = Decoder::new(set);
+
+    if ps2::send(Ps2Port::First, CMD_ENABLE_SCANNING) != Some(ps2::ACK) {
+        warn!("PS/2 keyboard didn't respond");
+        return false;
+    }
+    irq::register_irq(KEYBOARD_IRQ, keyboard_irq);
+    true
+}
+
+/// Change the keyboard layout.
+#[allow(dead_code)]
+pub fn set_keymap(keymap: &'static Keymap) {
+    interrupts::without_interrupts(|| KEYBOARD.lock().keymap = keymap);
+}
+
+/// Returns the next character typed, if there is one.
+pub fn read_char() -> Option<char> {
+    CHARS.pop()
+}
+
+fn keyboard_irq(_irq: u8) {
+    let byte = ps2::read_data();
+    let (event, c) = match KEYBOARD.lock().feed(byte) {
+        Some(event) => event,
+        None => return,
+    };
+    if let Some(c) = c {
+        // Drop characters if nobody is reading them
+        let _ = CHARS.push(c);
+    }
+    super::push_event(InputEvent::Key(event));
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Feed `bytes` to `decoder`, returning the keys decoded.
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<(KeyCode, bool)> {
+        bytes
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte))
+            .collect()
+    }
+
+    /// Feed `bytes` to `keyboard`, returning the characters typed.
+    fn type_bytes(keyboard: &mut Keyboard, bytes: &[u8]) -> Vec<char> {
+        bytes
+            .iter()
+            .filter_map(|&byte| keyboard.feed(byte))
+            .filter_map(|(_, c)| c)
+            .collect()
+    }
+
+    #[test_case]
+    fn set1_press_and_release() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        assert_eq!(
+            decode(&mut decoder, &[0x1e, 0x9e, 0x39, 0xb9]),
+            [
+                (KeyCode::A, true),
+                (KeyCode::A, false),
+                (KeyCode::Space, true),
+                (KeyCode::Space, false),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn set1_extended() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        // Right ctrl, then up with a fake shift around it (as sent with num lock on), then left
+        // ctrl to check the prefix doesn't stick
+        assert_eq!(
+            decode(
+                &mut decoder,
+                &[0xe0, 0x1d, 0xe0, 0x9d, 0xe0, 0x2a, 0xe0, 0x48, 0xe0, 0xc8, 0xe0, 0xaa, 0x1d]
+            ),
+            [
+                (KeyCode::RightCtrl, true),
+                (KeyCode::RightCtrl, false),
+                (KeyCode::Up, true),
+                (KeyCode::Up, false),
+                (KeyCode::LeftCtrl, true),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn set2_press_and_release() {
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        assert_eq!(
+            decode(&mut decoder, &[0x1c, 0xf0, 0x1c, 0x83, 0xf0, 0x83]),
+            [
+                (KeyCode::A, true),
+                (KeyCode::A, false),
+                (KeyCode::F7, true),
+                (KeyCode::F7, false),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn set2_extended() {
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        assert_eq!(
+            decode(&mut decoder, &[0xe0, 0x14, 0xe0, 0xf0, 0x14, 0x14]),
+            [
+                (KeyCode::RightCtrl, true),
+                (KeyCode::RightCtrl, false),
+                (KeyCode::LeftCtrl, true),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn pause() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        assert_eq!(
+            decode(&mut decoder, &[0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5, 0x1e]),
+            [(KeyCode::Pause, true), (KeyCode::A, true)]
+        );
+
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        assert_eq!(
+            decode(
+                &mut decoder,
+                &[0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77, 0x1c]
+            ),
+            [(KeyCode::Pause, true), (KeyCode::A, true)]
+        );
+    }
+
+    #[test_case]
+    fn modifiers() {
+        let mut keyboard = Keyboard::new(ScancodeSet::Set1, &keymap::US);
+        let events: Vec<KeyEvent> = [0x2a, 0x3a, 0xba, 0xaa]
+            .iter()
+            .filter_map(|&byte| keyboard.feed(byte))
+            .map(|(event, _)| event)
+            .collect();
+        let modifiers: Vec<Modifiers> = events.iter().map(|event| event.modifiers).collect();
+        // Shift is held until released, caps lock stays on after being released
+        assert_eq!(
+            modifiers,
+            [
+                Modifiers::LEFT_SHIFT,
+                Modifiers::LEFT_SHIFT | Modifiers::CAPS_LOCK,
+                Modifiers::LEFT_SHIFT | Modifiers::CAPS_LOCK,
+                Modifiers::CAPS_LOCK,
+            ]
+        );
+        assert_eq!(
+            events[3],
+            KeyEvent {
+                key: KeyCode::LeftShift,
+                pressed: false,
+                modifiers: Modifiers::CAPS_LOCK,
+            }
+        );
+    }
+
+    #[test_case]
+    fn typing() {
+        let mut keyboard = Keyboard::new(ScancodeSet::Set1, &keymap::US);
+        // a, shift+a, shift+2, caps lock, a, shift+a, caps lock, 2
+        let bytes = [
+            0x1e, 0x9e, 0x2a, 0x1e, 0x9e, 0x03, 0x83, 0xaa, 0x3a, 0xba, 0x1e, 0x9e, 0x2a, 0x1e,
+            0x9e, 0xaa, 0x3a, 0xba, 0x03, 0x83,
+        ];
+        assert_eq!(
+            type_bytes(&mut keyboard, &bytes),
+            ['a', 'A', '@', 'A', 'a', '2']
+        );
+
+        let mut keyboard = Keyboard::new(ScancodeSet::Set2, &keymap::UK);
+        // shift+2, caps lock, 2
+        let bytes = [0x12, 0x1e, 0xf0, 0x1e, 0xf0, 0x12, 0x58, 0xf0, 0x58, 0x1e];
+        assert_eq!(type_bytes(&mut keyboard, &bytes), ['"', '2']);
+    }
+}
@@ -0,0 +1,226 @@
+//! Keyboard layouts, translating keys to the characters printed on them.
+
+use super::keyboard::{KeyCode, Modifiers};
+
+/// The characters produced by a key.
+#[derive(Debug, Clone, Copy)]
+pub struct KeymapEntry {
+    pub key: KeyCode,
+    pub normal: char,
+    /// With shift held (or caps lock on, for letters).
+    pub shifted: char,
+    /// With AltGr (right alt) held, if anything.
+    pub alt_gr: Option<char>,
+}
+
+/// A keyboard layout, listing the characters produced by the keys that vary between layouts.
+/// Letters, space, enter, tab, backspace and the keypad are the same in all layouts.
+#[derive(Debug)]
+pub struct Keymap {
+    pub name: &'static str,
+    pub entries: &'static [KeymapEntry],
+}
+
+const fn entry(key: KeyCode, normal: char, shifted: char) -> KeymapEntry {
+    KeymapEntry {
+        key,
+        normal,
+        shifted,
+        alt_gr: None,
+    }
+}
+
+const fn entry_alt_gr(key: KeyCode, normal: char, shifted: char, alt_gr: char) -> KeymapEntry {
+    KeymapEntry {
+        key,
+        normal,
+        shifted,
+        alt_gr: Some(alt_gr),
+    }
+}
+
+/// The US layout.
+pub static US: Keymap = Keymap {
+    name: "us",
+    entries: &[
+        entry(KeyCode::Key1, '1', '!'),
+        entry(KeyCode::Key2, '2', '@'),
+        entry(KeyCode::Key3, '3', '#'),
+        entry(KeyCode::Key4, '4', '$'),
+        entry(KeyCode::Key5, '5', '%'),
+        entry(KeyCode::Key6, '6', '^'),
+        entry(KeyCode::Key7, '7', '&'),
+        entry(KeyCode::Key8, '8', '*'),
+        entry(KeyCode::Key9, '9', '('),
+        entry(KeyCode::Key0, '0', ')'),
+        entry(KeyCode::Minus, '-', '_'),
+        entry(KeyCode::Equals, '=', '+'),
+        entry(KeyCode::LeftBracket, '[', '{'),
+        entry(KeyCode::RightBracket, ']', '}'),
+        entry(KeyCode::Backslash, '\\', '|'),
+        entry(KeyCode::Semicolon, ';', ':'),
+        entry(KeyCode::Quote, '\'', '"'),
+        entry(KeyCode::Backquote, '`', '~'),
+        entry(KeyCode::Comma, ',', '<'),
+        entry(KeyCode::Period, '.', '>'),
+        entry(KeyCode::Slash, '/', '?'),
+        entry(KeyCode::NonUsBackslash, '\\', '|'),
+    ],
+};
+
+/// The UK layout.
+pub static UK: Keymap = Keymap {
+    name: "uk",
+    entries: &[
+        entry(KeyCode::Key1, '1', '!'),
+        entry(KeyCode::Key2, '2', '"'),
+        entry(KeyCode::Key3, '3', '£'),
+        entry_alt_gr(KeyCode::Key4, '4', '$', '€'),
+        entry(KeyCode::Key5, '5', '%'),
+        entry(KeyCode::Key6, '6', '^'),
+        entry(KeyCode::Key7, '7', '&'),
+        entry(KeyCode::Key8, '8', '*'),
+        entry(KeyCode::Key9, '9', '('),
+        entry(KeyCode::Key0, '0', ')'),
+        entry(KeyCode::Minus, '-', '_'),
+        entry(KeyCode::Equals, '=', '+'),
+        entry(KeyCode::LeftBracket, '[', '{'),
+        entry(KeyCode::RightBracket, ']', '}'),
+        entry(KeyCode::Backslash, '#', '~'),
+        entry(KeyCode::Semicolon, ';', ':'),
+        entry(KeyCode::Quote, '\'', '@'),
+        entry_alt_gr(KeyCode::Backquote, '`', '¬', '¦'),
+        entry(KeyCode::Comma, ',', '<'),
+        entry(KeyCode::Period, '.', '>'),
+        entry(KeyCode::Slash, '/', '?'),
+        entry(KeyCode::NonUsBackslash, '\\', '|'),
+    ],
+};
+
+/// All the built-in layouts.
+pub static KEYMAPS: &[&Keymap] = &[&US, &UK];
+
+/// Find a built-in layout by name, E.G. "uk".
+#[allow(dead_code)]
+pub fn find(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS.iter().copied().find(|keymap| keymap.name == name)
+}
+
+impl Keymap {
+    /// Translate a key press to a character, if it produces one.
+    pub fn translate(&self, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+        let shift = modifiers.intersects(Modifiers::SHIFT);
+
+        if let Some(letter) = key.letter() {
+            let upper = shift != modifiers.contains(Modifiers::CAPS_LOCK);
+            return Some(if modifiers.intersects(Modifiers::CTRL) {
+                // Control characters, E.G. Ctrl+C is 0x03
+                (letter as u8 - b'a' + 1) as char
+            } else if upper {
+                letter.to_ascii_uppercase()
+            } else {
+                letter
+            });
+        }
+
+        if let Some(c) = key.keypad_char(modifiers.contains(Modifiers::NUM_LOCK)) {
+            return Some(c);
+        }
+
+        match key {
+            KeyCode::Space => return Some(' '),
+            KeyCode::Enter | KeyCode::KeypadEnter => return Some('\n'),
+            KeyCode::Tab => return Some('\t'),
+            KeyCode::Backspace => return Some('\x08'),
+            KeyCode::Escape => return Some('\x1b'),
+            _ => {}
+        }
+
+        let entry = self.entries.iter().find(|entry| entry.key == key)?;
+        if modifiers.contains(Modifiers::RIGHT_ALT) {
+            entry.alt_gr
+        } else if shift {
+            Some(entry.shifted)
+        } else {
+            Some(entry.normal)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn letters() {
+        let keymap = &US;
+        let translate = |modifiers| keymap.translate(KeyCode::Q, modifiers);
+        assert_eq!(translate(Modifiers::empty()), Some('q'));
+        assert_eq!(translate(Modifiers::LEFT_SHIFT), Some('Q'));
+        assert_eq!(translate(Modifiers::RIGHT_SHIFT), Some('Q'));
+        assert_eq!(translate(Modifiers::CAPS_LOCK), Some('Q'));
+        // Shift undoes caps lock
+        assert_eq!(
+            translate(Modifiers::CAPS_LOCK | Modifiers::LEFT_SHIFT),
+            Some('q')
+        );
+        assert_eq!(translate(Modifiers::LEFT_CTRL), Some('\x11'));
+    }
+
+    #[test_case]
+    fn symbols() {
+        // Caps lock only affects letters
+        let cases = [
+            (&US, KeyCode::Key2, Modifiers::empty(), Some('2')),
+            (&US, KeyCode::Key2, Modifiers::LEFT_SHIFT, Some('@')),
+            (&US, KeyCode::Key2, Modifiers::CAPS_LOCK, Some('2')),
+            (&US, KeyCode::Quote, Modifiers::RIGHT_SHIFT, Some('"')),
+            (&US, KeyCode::Backslash, Modifiers::empty(), Some('\\')),
+            (&UK, KeyCode::Key2, Modifiers::LEFT_SHIFT, Some('"')),
+            (&UK, KeyCode::Key3, Modifiers::LEFT_SHIFT, Some('£')),
+            (&UK, KeyCode::Quote, Modifiers::LEFT_SHIFT, Some('@')),
+            (&UK, KeyCode::Backslash, Modifiers::empty(), Some('#')),
+            (&UK, KeyCode::Key4, Modifiers::RIGHT_ALT, Some('€')),
+            (&UK, KeyCode::Key5, Modifiers::RIGHT_ALT, None),
+        ];
+        for &(keymap, key, modifiers, c) in cases.iter() {
+            assert_eq!(
+                keymap.translate(key, modifiers),
+                c,
+                "{:?} {:?} in {}",
+                key,
+                modifiers,
+                keymap.name
+            );
+        }
+    }
+
+    #[test_case]
+    fn layout_independent_keys() {
+        for keymap in KEYMAPS.iter() {
+            assert_eq!(
+                keymap.translate(KeyCode::Enter, Modifiers::empty()),
+                Some('\n')
+            );
+            assert_eq!(
+                keymap.translate(KeyCode::Space, Modifiers::SHIFT),
+                Some(' ')
+            );
+            assert_eq!(keymap.translate(KeyCode::Keypad7, Modifiers::empty()), None);
+            assert_eq!(
+                keymap.translate(KeyCode::Keypad7, Modifiers::NUM_LOCK),
+                Some('7')
+            );
+            assert_eq!(
+                keymap.translate(KeyCode::LeftShift, Modifiers::empty()),
+                None
+            );
+        }
+    }
+
+    #[test_case]
+    fn find_layout() {
+        assert_eq!(find("uk").map(|keymap| keymap.name), Some("uk"));
+        assert!(find("dvorak").is_none());
+    }
+}
@@ -0,0 +1,64 @@
+//! A line editor, reading a line of text from any source of characters.
+
+use alloc::string::String;
+
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+
+/// Read a line of text one character at a time from `read_char`, echoing it with `echo`.
+/// Backspace and delete erase the last character, and other control characters are ignored. The
+/// line ending isn't included.
+pub fn read_line(mut read_char: impl FnMut() -> char, mut echo: impl FnMut(char)) -> String {
+    let mut line = String::new();
+    loop {
+        match read_char() {
+            '\r' | '\n' => {
+                echo('\n');
+                return line;
+            }
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    // Echoing a backspace also erases the character before the cursor
+                    echo(BACKSPACE);
+                }
+            }
+            c if !c.is_control() => {
+                line.push(c);
+                echo(c);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the editor on `input`, returning the line read and the characters echoed.
+    fn edit(input: &str) -> (String, String) {
+        let mut input = input.chars();
+        let mut echoed = String::new();
+        let line = read_line(|| input.next().unwrap(), |c| echoed.push(c));
+        (line, echoed)
+    }
+
+    #[test_case]
+    fn stops_at_line_ending() {
+        assert_eq!(edit("help\rmore"), ("help".into(), "help\n".into()));
+        assert_eq!(edit("\n"), (String::new(), "\n".into()));
+    }
+
+    #[test_case]
+    fn backspace_erases() {
+        assert_eq!(
+            edit("ab\x08c\x7f\x7f\x7fd\r"),
+            ("d".into(), "ab\x08c\x08\x08d\n".into())
+        );
+    }
+
+    #[test_case]
+    fn ignores_control_characters() {
+        assert_eq!(edit("a\x1b\tb\r"), ("ab".into(), "ab\n".into()));
+    }
+}
@@ -0,0 +1,70 @@
+//! Input devices, and the queue of events they produce.
+
+pub mod keyboard;
+pub mod keymap;
+pub mod line;
+pub mod mouse;
+pub mod ps2;
+
+use log::info;
+
+use self::{keyboard::KeyEvent, mouse::MouseButton};
+use crate::ring_buffer::RingBuffer;
+
+/// Something that happened on an input device.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    /// The mouse moved, or its wheel scrolled. Positive `dy` is down the screen, and positive
+    /// `wheel` is towards the user.
+    MouseMove {
+        dx: i16,
+        dy: i16,
+        wheel: i8,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+}
+
+/// Events from all input devices, waiting to be read.
+///
+/// This is only pushed to from IRQ handlers, which can't interrupt each other, so there is only
+/// ever one producer at a time.
+static EVENTS: RingBuffer<InputEvent, 256> = RingBuffer::new();
+
+/// Queue an event from an input device's IRQ handler. The event is dropped if the queue is full.
+fn push_event(event: InputEvent) {
+    let _ = EVENTS.push(event);
+}
+
+/// Returns the next input event, if there is one.
+#[allow(dead_code)]
+pub fn next_event() -> Option<InputEvent> {
+    EVENTS.pop()
+}
+
+/// Initialise the PS/2 controller and the devices connected to it.
+pub fn init() {
+    let controller = match ps2::init() {
+        Some(controller) => controller,
+        None => {
+            info!("No PS/2 controller found");
+            return;
+        }
+    };
+
+    // Both IRQs stay disabled in the controller until both devices are set up, as the devices share
+    // the controller's output buffer and the IRQ handlers would steal the responses to the setup
+    // commands
+    let keyboard = keyboard::init(controller);
+    let mouse = mouse::init(controller);
+    if keyboard {
+        ps2::set_irq_enabled(ps2::Ps2Port::First, true);
+    }
+    if mouse {
+        ps2::set_irq_enabled(ps2::Ps2Port::Second, true);
+    }
+}
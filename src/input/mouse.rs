@@ -0,0 +1,280 @@
+//! PS/2 mouse driver, parsing movement packets (with the IntelliMouse scroll wheel if present)
+//! into input events.
+
+use log::{info, warn};
+use spin::Mutex;
+
+use super::{
+    ps2::{self, Ps2Port},
+    InputEvent,
+};
+use crate::interrupts::irq;
+
+/// IRQ raised by the mouse.
+const MOUSE_IRQ: u8 = 12;
+
+const CMD_SET_SAMPLE_RATE: u8 = 0xf3;
+const CMD_GET_ID: u8 = 0xf2;
+const CMD_ENABLE_REPORTING: u8 = 0xf4;
+const CMD_SET_DEFAULTS: u8 = 0xf6;
+const CMD_RESET: u8 = 0xff;
+
+const RESET_PASSED: u8 = 0xaa;
+/// Device ID of a mouse with a scroll wheel, once it has been enabled.
+const ID_INTELLIMOUSE: u8 = 3;
+
+/// Bit of the first byte of a packet that is always set, used to find the start of packets.
+const PACKET_ALWAYS_SET: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+/// A mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    /// The buttons, in the order of their bits in the first byte of a packet.
+    const ALL: [Self; 3] = [Self::Left, Self::Right, Self::Middle];
+}
+
+/// A movement packet from the mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+    /// The button bits, in the order of [`MouseButton::ALL`].
+    buttons: u8,
+    /// Movement right, or 0 if it overflowed.
+    dx: i16,
+    /// Movement up, or 0 if it overflowed.
+    dy: i16,
+    /// Scroll wheel movement towards the user, or 0 if there's no wheel.
+    wheel: i8,
+}
+
+impl Packet {
+    /// Decode a packet. The last byte is only used if the mouse has a scroll wheel.
+    fn parse([flags, x, y, z]: [u8; 4], has_wheel: bool) -> Self {
+        // Movement is 9 bit two's complement, with the sign bits in the first byte
+        let movement = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else {
+                value as i16 - if flags & sign != 0 { 0x100 } else { 0 }
+            }
+        };
+        Self {
+            buttons: flags & 0b111,
+            dx: movement(x, PACKET_X_SIGN, PACKET_X_OVERFLOW),
+            dy: movement(y, PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+            wheel: if has_wheel { z as i8 } else { 0 },
+        }
+    }
+}
+
+/// Assembles the bytes from the mouse into packets.
+struct PacketParser {
+    bytes: [u8; 4],
+    /// Number of bytes of `bytes` received so far.
+    received: usize,
+    has_wheel: bool,
+}
+
+impl PacketParser {
+    const fn new(has_wheel: bool) -> Self {
+        Self {
+            bytes: [0; 4],
+            received: 0,
+            has_wheel,
+        }
+    }
+
+    /// 3, or 4 if the mouse has a scroll wheel.
+    fn packet_size(&self) -> usize {
+        if self.has_wheel {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Add the next byte, returning the packet once it has been received.
+    fn feed(&mut self, byte: u8) -> Option<Packet> {
+        // If we've lost track of where packets start, wait for something that looks like a start
+        if self.received == 0 && byte & PACKET_ALWAYS_SET == 0 {
+            return None;
+        }
+        self.bytes[self.received] = byte;
+        self.received += 1;
+        if self.received < self.packet_size() {
+            return None;
+        }
+        self.received = 0;
+        Some(Packet::parse(self.bytes, self.has_wheel))
+    }
+}
+
+/// State of the mouse driver, only touched by the IRQ handler once set up.
+struct Mouse {
+    parser: PacketParser,
+    /// The button bits from the last packet.
+    buttons: u8,
+}
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
+    parser: PacketParser::new(false),
+    buttons: 0,
+});
+
+/// Send `command` to the mouse, returning whether it was acknowledged.
+fn command(command: u8) -> bool {
+    ps2::send(Ps2Port::Second, command) == Some(ps2::ACK)
+}
+
+/// Set up the mouse on the second PS/2 port, and register its IRQ handler. Returns whether the
+/// mouse is ready for its IRQ to be enabled in the controller.
+pub fn init(controller: &ps2::Controller) -> bool {
+    if !controller.second_port {
+        info!("No PS/2 mouse found");
+        return false;
+    }
+
+    // Reset replies with the self test result and the device ID
+    if !command(CMD_RESET) || ps2::receive() != Some(RESET_PASSED) {
+        warn!("PS/2 mouse didn't respond");
+        return false;
+    }
+    ps2::receive();
+    command(CMD_SET_DEFAULTS);
+
+    // Setting this magic sequence of sample rates enables the scroll wheel, if there is one
+    for rate in [200, 100, 80] {
+        command(CMD_SET_SAMPLE_RATE);
+        command(rate);
+    }
+    let has_wheel = command(CMD_GET_ID) && ps2::receive() == Some(ID_INTELLIMOUSE);
+    MOUSE.lock().parser = PacketParser::new(has_wheel);
+
+    if !command(CMD_ENABLE_REPORTING) {
+        warn!("PS/2 mouse didn't respond");
+        return false;
+    }
+    irq::register_irq(MOUSE_IRQ, mouse_irq);
+    info!(
+        "PS/2 mouse enabled{}",
+        if has_wheel { ", with scroll wheel" } else { "" }
+    );
+    true
+}
+
+fn mouse_irq(_irq: u8) {
+    let byte = ps2::read_data();
+    let mut mouse = MOUSE.lock();
+    let packet = match mouse.parser.feed(byte) {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    for (bit, &button) in MouseButton::ALL.iter().enumerate() {
+        let mask = 1 << bit;
+        if (packet.buttons ^ mouse.buttons) & mask != 0 {
+            super::push_event(InputEvent::MouseButton {
+                button,
+                pressed: packet.buttons & mask != 0,
+            });
+        }
+    }
+    mouse.buttons = packet.buttons;
+
+    if packet.dx != 0 || packet.dy != 0 || packet.wheel != 0 {
+        super::push_event(InputEvent::MouseMove {
+            dx: packet.dx,
+            // The mouse counts up as positive, but the screen counts down
+            dy: -packet.dy,
+            wheel: packet.wheel,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Feed `bytes` to `parser`, returning the packets received.
+    fn parse(parser: &mut PacketParser, bytes: &[u8]) -> Vec<Packet> {
+        bytes.iter().filter_map(|&byte| parser.feed(byte)).collect()
+    }
+
+    const fn packet(buttons: u8, dx: i16, dy: i16, wheel: i8) -> Packet {
+        Packet {
+            buttons,
+            dx,
+            dy,
+            wheel,
+        }
+    }
+
+    #[test_case]
+    fn sign_extension() {
+        let mut parser = PacketParser::new(false);
+        assert_eq!(
+            parse(
+                &mut parser,
+                &[0x08, 0x05, 0x10, 0x38, 0xfb, 0x00, 0x39, 0x00, 0xff]
+            ),
+            [
+                packet(0, 5, 16, 0),
+                packet(0, -5, -256, 0),
+                packet(1, -256, -1, 0),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn overflow() {
+        let mut parser = PacketParser::new(false);
+        // The overflowing axis is dropped, but the other axis and the buttons are still reported
+        assert_eq!(
+            parse(&mut parser, &[0x4a, 0xff, 0x03, 0x88, 0x01, 0xff]),
+            [packet(2, 0, 3, 0), packet(0, 1, 0, 0)]
+        );
+    }
+
+    #[test_case]
+    fn wheel() {
+        let mut parser = PacketParser::new(true);
+        assert_eq!(
+            parse(
+                &mut parser,
+                &[0x08, 0x00, 0x00, 0x01, 0x0c, 0x00, 0x00, 0xff]
+            ),
+            [packet(0, 0, 0, 1), packet(4, 0, 0, -1)]
+        );
+        // Without a wheel, the 4th byte starts the next packet
+        let mut parser = PacketParser::new(false);
+        assert_eq!(
+            parse(&mut parser, &[0x08, 0x00, 0x00, 0x08, 0x02, 0x00]),
+            [packet(0, 0, 0, 0), packet(0, 2, 0, 0)]
+        );
+    }
+
+    #[test_case]
+    fn resync() {
+        let mut parser = PacketParser::new(false);
+        // The tail of a packet that started before we were listening is skipped, up to the next
+        // byte with the always-set bit
+        assert_eq!(
+            parse(
+                &mut parser,
+                &[0x05, 0x10, 0x09, 0x01, 0x02, 0x08, 0x03, 0x04]
+            ),
+            [packet(1, 1, 2, 0), packet(0, 3, 4, 0)]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Driver for the 8042 PS/2 controller, which the keyboard and mouse are connected to.
+
+use log::error;
+use spin::{Mutex, Once};
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status bit set when there is data to read from the data port.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status bit set while the controller hasn't consumed the last byte written to it.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_SECOND_PORT: u8 = 0xa7;
+const CMD_ENABLE_SECOND_PORT: u8 = 0xa8;
+const CMD_TEST_SECOND_PORT: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_TEST_FIRST_PORT: u8 = 0xab;
+const CMD_DISABLE_FIRST_PORT: u8 = 0xad;
+const CMD_ENABLE_FIRST_PORT: u8 = 0xae;
+const CMD_WRITE_SECOND_PORT: u8 = 0xd4;
+const CMD_PULSE_RESET: u8 = 0xfe;
+
+/// Configuration byte bit enabling IRQ 1 for the first port.
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+/// Configuration byte bit enabling IRQ 12 for the second port.
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+/// Configuration byte bit set when the second port's clock is disabled.
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+/// Configuration byte bit enabling translation of the first port's scancodes to set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Device response acknowledging a command.
+pub const ACK: u8 = 0xfa;
+/// Device response asking for the last byte to be sent again.
+pub const RESEND: u8 = 0xfe;
+
+/// Number of times to poll the status register before giving up on the controller.
+const TIMEOUT: usize = 100_000;
+
+/// One of the controller's two ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The first port, normally the keyboard.
+    First,
+    /// The second port, normally the mouse.
+    Second,
+}
+
+/// What was found when initialising the controller.
+#[derive(Debug)]
+pub struct Controller {
+    /// Whether the controller translates the keyboard's scancodes to set 1.
+    pub translation: bool,
+    /// Whether the first port works.
+    pub first_port: bool,
+    /// Whether there is a working second port.
+    pub second_port: bool,
+}
+
+static CONTROLLER: Once<Controller> = Once::new();
+/// Serialises commands to the controller.
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn status() -> u8 {
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+/// Wait for a byte from the controller or a device, and read it.
+fn read() -> Option<u8> {
+    (0..TIMEOUT)
+        .find(|_| status() & STATUS_OUTPUT_FULL != 0)
+        .map(|_| unsafe { Port::new(DATA_PORT).read() })
+}
+
+/// Wait for the controller to be ready, and write a byte to its data port.
+fn write(byte: u8) -> bool {
+    let ready = (0..TIMEOUT).any(|_| status() & STATUS_INPUT_FULL == 0);
+    if ready {
+        unsafe { Port::new(DATA_PORT).write(byte) };
+    }
+    ready
+}
+
+/// Send a command to the controller itself.
+fn command(command: u8) -> bool {
+    let ready = (0..TIMEOUT).any(|_| status() & STATUS_INPUT_FULL == 0);
+    if ready {
+        unsafe { Port::new(COMMAND_PORT).write(command) };
+    }
+    ready
+}
+
+/// Discard any bytes waiting to be read. Returns `false` if the output buffer never empties, E.G.
+/// because there's no controller and the status port reads as `0xff`.
+fn flush() -> bool {
+    (0..TIMEOUT).any(|_| {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            return true;
+        }
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+        false
+    })
+}
+
+/// Initialise the controller, and enable whichever ports work (with their IRQs disabled). Returns
+/// `None` if there's no working controller.
+pub fn init() -> Option<&'static Controller> {
+    let _lock = LOCK.lock();
+
+    // Stop the devices sending anything while we set things up
+    command(CMD_DISABLE_FIRST_PORT);
+    command(CMD_DISABLE_SECOND_PORT);
+    if !flush() {
+        return None;
+    }
+
+    command(CMD_READ_CONFIG);
+    let mut config = read()?;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+    command(CMD_WRITE_CONFIG);
+    write(config);
+
+    command(CMD_SELF_TEST);
+    if read()? != SELF_TEST_PASSED {
+        error!("PS/2 controller failed its self test");
+        return None;
+    }
+    // The self test can reset the controller
+    command(CMD_WRITE_CONFIG);
+    write(config);
+
+    // If the second port's clock was disabled, and enabling the port enables it, it exists
+    let mut second_port = false;
+    if config & CONFIG_SECOND_CLOCK_DISABLED != 0 {
+        command(CMD_ENABLE_SECOND_PORT);
+        command(CMD_READ_CONFIG);
+        second_port = read()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        command(CMD_DISABLE_SECOND_PORT);
+    }
+
+    command(CMD_TEST_FIRST_PORT);
+    let first_port = read() == Some(PORT_TEST_PASSED);
+    if second_port {
+        command(CMD_TEST_SECOND_PORT);
+        second_port = read() == Some(PORT_TEST_PASSED);
+    }
+
+    if first_port {
+        command(CMD_ENABLE_FIRST_PORT);
+    }
+    if second_port {
+        command(CMD_ENABLE_SECOND_PORT);
+    }
+
+    Some(CONTROLLER.call_once(|| Controller {
+        translation: config & CONFIG_TRANSLATION != 0,
+        first_port,
+        second_port,
+    }))
+}
+
+/// Returns the controller's configuration, if it was initialised successfully.
+#[allow(dead_code)]
+pub fn controller() -> Option<&'static Controller> {
+    CONTROLLER.get()
+}
+
+/// Enable or disable the IRQ for `port` (IRQ 1 for the first port, IRQ 12 for the second).
+pub fn set_irq_enabled(port: Ps2Port, enabled: bool) {
+    let _lock = LOCK.lock();
+    let bit = match port {
+        Ps2Port::First => CONFIG_FIRST_IRQ,
+        Ps2Port::Second => CONFIG_SECOND_IRQ,
+    };
+    command(CMD_READ_CONFIG);
+    if let Some(config) = read() {
+        command(CMD_WRITE_CONFIG);
+        write(if enabled { config | bit } else { config & !bit });
+    }
+}
+
+/// Send `byte` to the device on `port`, resending it if asked, and return the device's response
+/// (normally [`ACK`]). The port's IRQ must be disabled, or the response will go to the IRQ
+/// handler instead.
+pub fn send(port: Ps2Port, byte: u8) -> Option<u8> {
+    let _lock = LOCK.lock();
+    for _ in 0..3 {
+        if port == Ps2Port::Second {
+            command(CMD_WRITE_SECOND_PORT);
+        }
+        if !write(byte) {
+            return None;
+        }
+        match read()? {
+            RESEND => continue,
+            response => return Some(response),
+        }
+    }
+    None
+}
+
+/// Wait for a byte from a device, E.G. the rest of a response to [`send`].
+pub fn receive() -> Option<u8> {
+    let _lock = LOCK.lock();
+    read()
+}
+
+/// Read a byte that a device has sent, from an IRQ handler.
+pub fn read_data() -> u8 {
+    unsafe { Port::new(DATA_PORT).read() }
+}
+
+/// Reset the CPU by pulsing the controller's reset line. Returns if the controller doesn't respond.
+pub fn pulse_reset() {
+    command(CMD_PULSE_RESET);
+}
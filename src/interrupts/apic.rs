@@ -0,0 +1,219 @@
+//! Driver for the local APIC of the current processor, in either xAPIC or x2APIC mode.
+
+use core::{arch::x86_64::__cpuid, ptr};
+
+use log::{info, warn};
+use spin::Once;
+use x86_64::{registers::model_specific::Msr, structures::idt::InterruptStackFrame};
+
+use super::{ioapic, irq};
+use crate::{
+    acpi::Madt,
+    memory::{paging::EntryFlags, Size4K, SizedRegion, VirtAddr},
+};
+
+/// The IA32_APIC_BASE MSR.
+const APIC_BASE_MSR: u32 = 0x1b;
+/// Bit of IA32_APIC_BASE enabling the local APIC.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// Bit of IA32_APIC_BASE switching the local APIC to x2APIC mode.
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+/// The MSR of the first register in x2APIC mode.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Vector of the spurious interrupts raised by the local APIC.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+/// Bit of the spurious interrupt vector register enabling the local APIC.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+/// Bit of the LVT registers masking the interrupt.
+pub const LVT_MASKED: u32 = 1 << 16;
+/// Bit of the interrupt command register set while an IPI is being sent (xAPIC only).
+const ICR_SEND_PENDING: u32 = 1 << 12;
+
+/// The local APIC registers, with their offsets in xAPIC mode.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(usize)]
+pub enum Register {
+    Id = 0x20,
+    Version = 0x30,
+    TaskPriority = 0x80,
+    EndOfInterrupt = 0xb0,
+    SpuriousVector = 0xf0,
+    ErrorStatus = 0x280,
+    InterruptCommand = 0x300,
+    InterruptCommandHigh = 0x310,
+    LvtTimer = 0x320,
+    LvtLint0 = 0x350,
+    LvtLint1 = 0x360,
+    LvtError = 0x370,
+    TimerInitialCount = 0x380,
+    TimerCurrentCount = 0x390,
+    TimerDivide = 0x3e0,
+}
+
+/// An inter-processor interrupt.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum Ipi {
+    /// Raise the given vector.
+    Fixed(u8),
+    /// Raise a non-maskable interrupt.
+    Nmi,
+    /// Reset the processor into its wait-for-SIPI state.
+    Init,
+    /// Start the processor in real mode, at the page with the given number.
+    Startup(u8),
+}
+
+impl Ipi {
+    /// The low 32 bits of the interrupt command register for this IPI.
+    fn command(self) -> u32 {
+        match self {
+            Self::Fixed(vector) => vector as u32,
+            Self::Nmi => 0b100 << 8,
+            // Level assert
+            Self::Init => 0b101 << 8 | 1 << 14,
+            Self::Startup(page) => 0b110 << 8 | page as u32,
+        }
+    }
+}
+
+/// How the local APIC registers are accessed.
+enum Mode {
+    /// Through memory-mapped registers at the given address.
+    XApic(VirtAddr),
+    /// Through MSRs.
+    X2Apic,
+}
+
+/// The local APIC of the current processor.
+pub struct LocalApic {
+    mode: Mode,
+}
+
+impl LocalApic {
+    pub fn read(&self, register: Register) -> u32 {
+        match self.mode {
+            // Safety: The registers are mapped in `init`
+            Mode::XApic(base) => unsafe {
+                ptr::read_volatile((base + register as usize).as_ptr::<u32>())
+            },
+            Mode::X2Apic => unsafe { Self::msr(register).read() as u32 },
+        }
+    }
+
+    pub fn write(&self, register: Register, value: u32) {
+        match self.mode {
+            // Safety: The registers are mapped in `init`
+            Mode::XApic(base) => unsafe {
+                ptr::write_volatile((base + register as usize).as_mut_ptr::<u32>(), value)
+            },
+            Mode::X2Apic => unsafe { Self::msr(register).write(value as u64) },
+        }
+    }
+
+    /// The MSR of `register` in x2APIC mode.
+    fn msr(register: Register) -> Msr {
+        Msr::new(X2APIC_MSR_BASE + (register as usize >> 4) as u32)
+    }
+
+    /// The ID of this local APIC, used to address IPIs and I/O APIC interrupts to it.
+    pub fn id(&self) -> u32 {
+        match self.mode {
+            Mode::XApic(_) => self.read(Register::Id) >> 24,
+            Mode::X2Apic => self.read(Register::Id),
+        }
+    }
+
+    /// Signal the end of the handler for the current interrupt.
+    pub fn end_of_interrupt(&self) {
+        self.write(Register::EndOfInterrupt, 0);
+    }
+
+    /// Send `ipi` to the processor with local APIC ID `destination`.
+    #[allow(dead_code)]
+    pub fn send_ipi(&self, destination: u32, ipi: Ipi) {
+        match self.mode {
+            Mode::XApic(_) => {
+                self.write(Register::InterruptCommandHigh, destination << 24);
+                self.write(Register::InterruptCommand, ipi.command());
+                while self.read(Register::InterruptCommand) & ICR_SEND_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+            // The whole command is written at once to a single 64-bit MSR
+            Mode::X2Apic => unsafe {
+                Self::msr(Register::InterruptCommand)
+                    .write((destination as u64) << 32 | ipi.command() as u64)
+            },
+        }
+    }
+}
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+/// Returns the local APIC, if interrupts are being routed through the APICs.
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+/// Returns whether the processor has a local APIC, and whether it supports x2APIC mode.
+fn detect() -> (bool, bool) {
+    let features = __cpuid(1);
+    (features.edx & 1 << 9 != 0, features.ecx & 1 << 21 != 0)
+}
+
+/// Switch interrupt handling from the 8259 PICs to the local and I/O APICs, if the processor and
+/// firmware support them. Must be called after [`crate::acpi::init`].
+pub fn init() {
+    let (has_apic, has_x2apic) = detect();
+    let madt = match Madt::get() {
+        Some(madt) if has_apic => madt,
+        _ => {
+            info!("No APIC found, using the 8259 PIC");
+            return;
+        }
+    };
+
+    let mut base_msr = Msr::new(APIC_BASE_MSR);
+    let mode = if has_x2apic {
+        unsafe { base_msr.write(base_msr.read() | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+        Mode::X2Apic
+    } else {
+        let addr = match madt.local_apic_addr() {
+            Some(addr) => addr,
+            None => {
+                warn!("No valid local APIC address, using the 8259 PIC");
+                return;
+            }
+        };
+        let addr = unsafe {
+            let addr = crate::memory::controller().identity_map(
+                addr..addr + Size4K::SIZE,
+                EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXEC,
+            );
+            base_msr.write(base_msr.read() | APIC_BASE_ENABLE);
+            addr
+        };
+        Mode::XApic(addr)
+    };
+
+    let lapic = LOCAL_APIC.call_once(|| LocalApic { mode });
+    lapic.write(Register::TaskPriority, 0);
+    lapic.write(Register::LvtError, LVT_MASKED);
+    lapic.write(
+        Register::SpuriousVector,
+        SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+
+    ioapic::init(&madt, lapic.id());
+    irq::use_apic(madt.has_8259);
+    info!(
+        "Using the {} and I/O APIC",
+        if has_x2apic { "x2APIC" } else { "xAPIC" }
+    );
+}
+
+/// Handler for spurious interrupts from the local APIC, which must not be acknowledged.
+pub(super) extern "x86-interrupt" fn spurious_handler(_: InterruptStackFrame) {}
@@ -0,0 +1,680 @@
+//! Handlers for the CPU exceptions (vectors 0-31).
+//!
+//! Every handler first calls the override registered for its exception with
+//! [`set_exception_handler`], if any, and falls back to a default handler that reports the
+//! exception. Breakpoint and debug exceptions are reported and then resumed, everything else is
+//! fatal.
+//!
+//! The handlers aren't `x86-interrupt` functions, as those don't expose the general purpose
+//! registers of the interrupted code. Instead each one has a naked entry point that saves them,
+//! so that they can be included in the report and overrides (E.G. a debugger) can inspect and
+//! modify them.
+
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::{
+    instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS},
+    registers::{
+        control::{Cr0, Cr2, Cr3, Cr4},
+        model_specific::{Efer, FsBase, GsBase},
+    },
+    structures::idt::{DescriptorTable, InterruptStackFrame, SelectorErrorCode},
+};
+
+use crate::{backtrace::Symbolized, gdt::tss, memory::VirtAddr, println};
+
+/// The architecturally defined exceptions that have an entry in the IDT.
+///
+/// Control protection (#CP, vector 21) and hypervisor injection (#HV, vector 28) exceptions aren't
+/// included, as the x86_64 crate doesn't expose their IDT entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    DivideError = 0,
+    Debug = 1,
+    NonMaskableInterrupt = 2,
+    Breakpoint = 3,
+    Overflow = 4,
+    BoundRangeExceeded = 5,
+    InvalidOpcode = 6,
+    DeviceNotAvailable = 7,
+    DoubleFault = 8,
+    InvalidTss = 10,
+    SegmentNotPresent = 11,
+    StackSegmentFault = 12,
+    GeneralProtectionFault = 13,
+    PageFault = 14,
+    X87FloatingPoint = 16,
+    AlignmentCheck = 17,
+    MachineCheck = 18,
+    SimdFloatingPoint = 19,
+    Virtualization = 20,
+    VmmCommunication = 29,
+    Security = 30,
+}
+
+impl Exception {
+    /// All the exceptions, in vector order.
+    pub const ALL: [Self; 21] = [
+        Self::DivideError,
+        Self::Debug,
+        Self::NonMaskableInterrupt,
+        Self::Breakpoint,
+        Self::Overflow,
+        Self::BoundRangeExceeded,
+        Self::InvalidOpcode,
+        Self::DeviceNotAvailable,
+        Self::DoubleFault,
+        Self::InvalidTss,
+        Self::SegmentNotPresent,
+        Self::StackSegmentFault,
+        Self::GeneralProtectionFault,
+        Self::PageFault,
+        Self::X87FloatingPoint,
+        Self::AlignmentCheck,
+        Self::MachineCheck,
+        Self::SimdFloatingPoint,
+        Self::Virtualization,
+        Self::VmmCommunication,
+        Self::Security,
+    ];
+
+    /// The exception with interrupt vector `vector`, if there is one.
+    pub fn from_vector(vector: u8) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|exception| exception.vector() == vector)
+    }
+
+    /// The interrupt vector of this exception.
+    pub fn vector(self) -> u8 {
+        self as u8
+    }
+
+    /// Human-readable name of this exception.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DivideError => "Divide error",
+            Self::Debug => "Debug",
+            Self::NonMaskableInterrupt => "Non-maskable interrupt",
+            Self::Breakpoint => "Breakpoint",
+            Self::Overflow => "Overflow",
+            Self::BoundRangeExceeded => "Bound range exceeded",
+            Self::InvalidOpcode => "Invalid opcode",
+            Self::DeviceNotAvailable => "Device not available",
+            Self::DoubleFault => "Double fault",
+            Self::InvalidTss => "Invalid TSS",
+            Self::SegmentNotPresent => "Segment not present",
+            Self::StackSegmentFault => "Stack segment fault",
+            Self::GeneralProtectionFault => "General protection fault",
+            Self::PageFault => "Page fault",
+            Self::X87FloatingPoint => "x87 floating point exception",
+            Self::AlignmentCheck => "Alignment check",
+            Self::MachineCheck => "Machine check",
+            Self::SimdFloatingPoint => "SIMD floating point exception",
+            Self::Virtualization => "Virtualization exception",
+            Self::VmmCommunication => "VMM communication exception",
+            Self::Security => "Security exception",
+        }
+    }
+
+    /// The short mnemonic of this exception, E.G. `#GP`.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::DivideError => "#DE",
+            Self::Debug => "#DB",
+            Self::NonMaskableInterrupt => "NMI",
+            Self::Breakpoint => "#BP",
+            Self::Overflow => "#OF",
+            Self::BoundRangeExceeded => "#BR",
+            Self::InvalidOpcode => "#UD",
+            Self::DeviceNotAvailable => "#NM",
+            Self::DoubleFault => "#DF",
+            Self::InvalidTss => "#TS",
+            Self::SegmentNotPresent => "#NP",
+            Self::StackSegmentFault => "#SS",
+            Self::GeneralProtectionFault => "#GP",
+            Self::PageFault => "#PF",
+            Self::X87FloatingPoint => "#MF",
+            Self::AlignmentCheck => "#AC",
+            Self::MachineCheck => "#MC",
+            Self::SimdFloatingPoint => "#XM",
+            Self::Virtualization => "#VE",
+            Self::VmmCommunication => "#VC",
+            Self::Security => "#SX",
+        }
+    }
+
+    /// Whether the error code pushed for this exception is a segment selector error code.
+    fn has_selector_error_code(self) -> bool {
+        matches!(
+            self,
+            Self::InvalidTss
+                | Self::SegmentNotPresent
+                | Self::StackSegmentFault
+                | Self::GeneralProtectionFault
+        )
+    }
+
+    /// Whether the interrupted code can be resumed after the default handler reports this
+    /// exception.
+    fn is_recoverable(self) -> bool {
+        matches!(self, Self::Breakpoint | Self::Debug)
+    }
+
+    /// Whether this exception is an abort, after which execution can't be resumed at all.
+    fn is_abort(self) -> bool {
+        matches!(self, Self::DoubleFault | Self::MachineCheck)
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, vector {})",
+            self.name(),
+            self.mnemonic(),
+            self.vector()
+        )
+    }
+}
+
+/// Everything known about an exception when its handler is called.
+pub struct ExceptionContext<'a> {
+    pub exception: Exception,
+    /// The stack frame pushed by the CPU. Handlers may modify it (see
+    /// [`InterruptStackFrame::as_mut`]) to change where execution resumes.
+    pub frame: &'a mut InterruptStackFrame,
+    /// The error code pushed by the CPU, for exceptions that have one.
+    pub error_code: Option<u64>,
+    /// Registers captured on entry to the handler.
+    pub registers: Registers,
+    /// The general purpose registers of the interrupted code. Changes to them take effect when
+    /// execution resumes.
+    pub general: &'a mut GeneralRegisters,
+}
+
+impl fmt::Display for ExceptionContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {}", self.exception)?;
+        if let Some(error_code) = self.error_code {
+            write!(f, "Error code: {:#x}", error_code)?;
+            if self.exception.has_selector_error_code() {
+                write!(
+                    f,
+                    " ({})",
+                    DecodedSelector(SelectorErrorCode::new_truncate(error_code))
+                )?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "{:#?}", self.frame)?;
+        writeln!(f, "{}", self.general)?;
+        write!(f, "{}", self.registers)
+    }
+}
+
+/// Human-readable description of a [`SelectorErrorCode`].
+struct DecodedSelector(SelectorErrorCode);
+
+impl fmt::Display for DecodedSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+        if code.is_null() {
+            return f.write_str("not caused by a selector");
+        }
+        let table = match code.descriptor_table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+        write!(f, "selector index {} in the {}", code.index(), table)?;
+        if code.external() {
+            f.write_str(", external event")?;
+        }
+        Ok(())
+    }
+}
+
+/// The system registers at the time of an exception.
+///
+/// General purpose registers aren't included, as they have to be saved by the entry point before
+/// the handler clobbers them. See [`GeneralRegisters`].
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+    pub cs: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub gs: u16,
+    pub ss: u16,
+    pub fs_base: u64,
+    pub gs_base: u64,
+}
+
+impl Registers {
+    /// Read the current values of the registers.
+    pub fn capture() -> Self {
+        Self {
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read().as_u64(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+            efer: Efer::read_raw(),
+            cs: CS::get_reg().0,
+            ds: DS::get_reg().0,
+            es: ES::get_reg().0,
+            fs: FS::get_reg().0,
+            gs: GS::get_reg().0,
+            ss: SS::get_reg().0,
+            fs_base: FsBase::read().as_u64(),
+            gs_base: GsBase::read().as_u64(),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "CR0={:#018x} CR2={:#018x} CR3={:#018x}",
+            self.cr0, self.cr2, self.cr3
+        )?;
+        writeln!(
+            f,
+            "CR4={:#018x} EFER={:#018x} FS.base={:#018x} GS.base={:#018x}",
+            self.cr4, self.efer, self.fs_base, self.gs_base
+        )?;
+        write!(
+            f,
+            "CS={:#06x} DS={:#06x} ES={:#06x} FS={:#06x} GS={:#06x} SS={:#06x}",
+            self.cs, self.ds, self.es, self.fs, self.gs, self.ss
+        )
+    }
+}
+
+/// The general purpose registers, in the order the exception entry points push them (so `rax`,
+/// pushed first, is last). `rsp` is in the interrupt stack frame instead.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct GeneralRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+impl fmt::Display for GeneralRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RAX={:#018x} RBX={:#018x} RCX={:#018x} RDX={:#018x}",
+            self.rax, self.rbx, self.rcx, self.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI={:#018x} RDI={:#018x} RBP={:#018x} R8 ={:#018x}",
+            self.rsi, self.rdi, self.rbp, self.r8
+        )?;
+        writeln!(
+            f,
+            "R9 ={:#018x} R10={:#018x} R11={:#018x} R12={:#018x}",
+            self.r9, self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13={:#018x} R14={:#018x} R15={:#018x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
+
+/// A function overriding the default handling of an exception. It returns `true` if it handled
+/// the exception, in which case execution resumes at the address in the stack frame, or `false`
+/// to fall back to the default handler.
+///
+/// Overrides for double faults and machine checks can't resume execution, so their return value
+/// is ignored and the default handler always runs afterwards.
+pub type ExceptionHandler = fn(context: &mut ExceptionContext) -> bool;
+
+/// Overridden handlers, indexed by vector.
+static OVERRIDES: Mutex<[Option<ExceptionHandler>; 32]> = Mutex::new([None; 32]);
+
+/// Override the handler for `exception`, returning the previous override (so that it can be
+/// called as a fallback).
+///
+/// # Panics
+///
+/// * If `exception` is a page fault. Use
+///   [`set_page_fault_resolver`](super::page_fault::set_page_fault_resolver) instead.
+pub fn set_exception_handler(
+    exception: Exception,
+    handler: ExceptionHandler,
+) -> Option<ExceptionHandler> {
+    assert_ne!(
+        exception,
+        Exception::PageFault,
+        "Page faults are routed through the page fault resolver"
+    );
+    OVERRIDES.lock()[exception.vector() as usize].replace(handler)
+}
+
+/// Remove the override for `exception`, returning it.
+#[allow(dead_code)]
+pub fn clear_exception_handler(exception: Exception) -> Option<ExceptionHandler> {
+    OVERRIDES.lock()[exception.vector() as usize].take()
+}
+
+/// Common body of the exception handlers.
+fn handle(
+    exception: Exception,
+    frame: &mut InterruptStackFrame,
+    error_code: Option<u64>,
+    general: &mut GeneralRegisters,
+) {
+    let mut context = ExceptionContext {
+        exception,
+        frame,
+        error_code,
+        registers: Registers::capture(),
+        general,
+    };
+
+    // Copy the override out, so the lock isn't held while it runs
+    let handler = OVERRIDES.lock()[exception.vector() as usize];
+    if let Some(handler) = handler {
+        if handler(&mut context) && !exception.is_abort() {
+            return;
+        }
+    }
+
+    println!("{}", context);
+    println!(
+        "Faulting instruction: {}",
+        Symbolized(context.frame.instruction_pointer.as_u64() as usize)
+    );
+    // Overflowing an IST stack usually ends up here rather than in the page fault handler, as the
+    // page fault can't be delivered on the overflowed stack either
+    if exception == Exception::DoubleFault {
+        if let Some(stack) =
+            VirtAddr::try_new(context.registers.cr2 as usize).and_then(tss::ist_guard_page)
+        {
+            panic!("IST stack overflow ({} stack)", stack);
+        }
+    }
+    if !exception.is_recoverable() {
+        panic!("Unhandled {}", exception);
+    }
+}
+
+/// Define an exception entry point, `$name`, that saves the general purpose registers of the
+/// interrupted code and runs `$body` with them, the stack frame and the error code. Exceptions
+/// that don't push an error code (without the `error_code` argument) get 0.
+///
+/// The entry point isn't an `x86-interrupt` function, so it must be installed with
+/// [`set_handler_addr`](x86_64::structures::idt::Entry::set_handler_addr).
+macro_rules! entry_point {
+    ($name:ident, |$general:tt, $frame:tt, $error_code:tt| $body:expr) => {
+        $crate::interrupts::exceptions::entry_point!(
+            @entry $name, "push 0", |$general, $frame, $error_code| $body
+        );
+    };
+    ($name:ident, error_code, |$general:tt, $frame:tt, $error_code:tt| $body:expr) => {
+        $crate::interrupts::exceptions::entry_point!(
+            @entry $name, |$general, $frame, $error_code| $body
+        );
+    };
+    (@entry $name:ident, $($push_error_code:literal,)? |$general:tt, $frame:tt, $error_code:tt| $body:expr) => {
+        #[unsafe(naked)]
+        pub(super) extern "C" fn $name() {
+            extern "C" fn inner(
+                $general: &mut $crate::interrupts::exceptions::GeneralRegisters,
+                $frame: &mut x86_64::structures::idt::InterruptStackFrame,
+                $error_code: u64,
+            ) {
+                $body
+            }
+
+            core::arch::naked_asm!(
+                $($push_error_code,)?
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "cld",
+                "mov rdi, rsp",
+                "lea rsi, [rsp + 16 * 8]",
+                "mov rdx, [rsp + 15 * 8]",
+                // The CPU aligns the stack before pushing the 5 word stack frame, so after the
+                // error code and 15 registers it's a word off
+                "sub rsp, 8",
+                "call {inner}",
+                "add rsp, 8",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                // Drop the error code
+                "add rsp, 8",
+                "iretq",
+                inner = sym inner,
+            );
+        }
+    };
+}
+pub(super) use entry_point;
+
+/// Define the entry point for an exception that doesn't push an error code.
+macro_rules! handler {
+    ($name:ident, $exception:ident) => {
+        entry_point!($name, |general, frame, _| {
+            handle(Exception::$exception, frame, None, general)
+        });
+    };
+}
+
+/// Define the entry point for an exception that pushes an error code.
+macro_rules! handler_with_err_code {
+    ($name:ident, $exception:ident) => {
+        entry_point!($name, error_code, |general, frame, error_code| {
+            handle(Exception::$exception, frame, Some(error_code), general)
+        });
+    };
+}
+
+handler!(divide_error_handler, DivideError);
+handler!(debug_handler, Debug);
+handler!(nmi_handler, NonMaskableInterrupt);
+handler!(breakpoint_handler, Breakpoint);
+handler!(overflow_handler, Overflow);
+handler!(bound_range_exceeded_handler, BoundRangeExceeded);
+handler!(invalid_opcode_handler, InvalidOpcode);
+handler!(device_not_available_handler, DeviceNotAvailable);
+handler_with_err_code!(invalid_tss_handler, InvalidTss);
+handler_with_err_code!(segment_not_present_handler, SegmentNotPresent);
+handler_with_err_code!(stack_segment_fault_handler, StackSegmentFault);
+handler_with_err_code!(general_protection_fault_handler, GeneralProtectionFault);
+handler!(x87_floating_point_handler, X87FloatingPoint);
+handler_with_err_code!(alignment_check_handler, AlignmentCheck);
+handler!(simd_floating_point_handler, SimdFloatingPoint);
+handler!(virtualization_handler, Virtualization);
+handler_with_err_code!(vmm_communication_handler, VmmCommunication);
+handler_with_err_code!(security_handler, Security);
+
+entry_point!(
+    double_fault_handler,
+    error_code,
+    |general, frame, error_code| {
+        handle(Exception::DoubleFault, frame, Some(error_code), general);
+        unreachable!("Returned from a double fault");
+    }
+);
+
+entry_point!(machine_check_handler, |general, frame, _| {
+    handle(Exception::MachineCheck, frame, None, general);
+    unreachable!("Returned from a machine check");
+});
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        arch::asm,
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    };
+
+    use super::*;
+
+    /// Put back the override for `exception` replaced by a test.
+    fn restore_handler(exception: Exception, previous: Option<ExceptionHandler>) {
+        match previous {
+            Some(handler) => {
+                set_exception_handler(exception, handler);
+            }
+            None => {
+                clear_exception_handler(exception);
+            }
+        }
+    }
+
+    #[test_case]
+    fn breakpoint_resumes() {
+        x86_64::instructions::interrupts::int3();
+    }
+
+    #[test_case]
+    fn breakpoint_override_changes_registers() {
+        fn set_rax(context: &mut ExceptionContext) -> bool {
+            context.general.rax = 42;
+            true
+        }
+
+        let previous = set_exception_handler(Exception::Breakpoint, set_rax);
+        let rax: u64;
+        // Safety: The override only changes RAX, which is an output
+        unsafe { asm!("int3", inout("rax") 0_u64 => rax) };
+        restore_handler(Exception::Breakpoint, previous);
+        assert_eq!(rax, 42);
+    }
+
+    #[test_case]
+    fn trap_flag_raises_debug_exception() {
+        static STEPPED: AtomicBool = AtomicBool::new(false);
+
+        fn stepped(context: &mut ExceptionContext) -> bool {
+            STEPPED.store(true, Ordering::SeqCst);
+            // Safety: Clearing the trap flag only stops single-stepping
+            unsafe {
+                context
+                    .frame
+                    .as_mut()
+                    .update(|frame| frame.cpu_flags &= !(1 << 8))
+            };
+            true
+        }
+
+        let previous = set_exception_handler(Exception::Debug, stepped);
+        // Safety: Setting the trap flag raises a debug exception after the next instruction,
+        // and the override clears it again
+        unsafe { asm!("pushfq", "or qword ptr [rsp], 0x100", "popfq", "nop") };
+        restore_handler(Exception::Debug, previous);
+        assert!(STEPPED.load(Ordering::SeqCst));
+    }
+
+    #[test_case]
+    fn fatal_exception_override_sees_registers() {
+        static RAX: AtomicU64 = AtomicU64::new(0);
+
+        fn skip_ud2(context: &mut ExceptionContext) -> bool {
+            RAX.store(context.general.rax, Ordering::SeqCst);
+            // Safety: Execution resumes after the 2 byte `ud2`
+            unsafe {
+                context
+                    .frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer += 2u64)
+            };
+            true
+        }
+
+        let previous = set_exception_handler(Exception::InvalidOpcode, skip_ud2);
+        // Safety: The override skips the `ud2`
+        unsafe { asm!("ud2", in("rax") 0x1234_u64) };
+        restore_handler(Exception::InvalidOpcode, previous);
+        assert_eq!(RAX.load(Ordering::SeqCst), 0x1234);
+    }
+
+    #[test_case]
+    fn error_code_exception_override_sees_registers() {
+        static RCX: AtomicU64 = AtomicU64::new(0);
+        static ERROR_CODE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+        fn skip_load(context: &mut ExceptionContext) -> bool {
+            RCX.store(context.general.rcx, Ordering::SeqCst);
+            ERROR_CODE.store(context.error_code.unwrap(), Ordering::SeqCst);
+            // Safety: Execution resumes after the 3 byte `mov rax, [rcx]`
+            unsafe {
+                context
+                    .frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer += 3u64)
+            };
+            true
+        }
+
+        const NON_CANONICAL: u64 = 0x8000_0000_0000_0000;
+        let previous = set_exception_handler(Exception::GeneralProtectionFault, skip_load);
+        // Safety: Loading from a non-canonical address raises #GP, and the override skips the
+        // load
+        unsafe {
+            asm!(
+                ".byte 0x48, 0x8b, 0x01", // mov rax, [rcx]
+                in("rcx") NON_CANONICAL,
+                out("rax") _,
+            )
+        };
+        restore_handler(Exception::GeneralProtectionFault, previous);
+        assert_eq!(RCX.load(Ordering::SeqCst), NON_CANONICAL);
+        assert_eq!(ERROR_CODE.load(Ordering::SeqCst), 0);
+    }
+}
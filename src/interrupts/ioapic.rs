@@ -0,0 +1,155 @@
+//! Driver for the I/O APICs, which route the legacy ISA IRQs (and other global system
+//! interrupts) to the local APICs.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use spin::{Mutex, Once};
+
+use super::pic::{IRQ_BASE, IRQ_COUNT};
+use crate::{
+    acpi::{Madt, MadtEntry},
+    memory::{paging::EntryFlags, Size4K, SizedRegion, VirtAddr},
+};
+
+/// Register holding the number of redirection entries, in bits 16-23.
+const REG_VERSION: u32 = 0x01;
+/// Register holding the low 32 bits of the first redirection entry.
+const REG_REDIRECTION_BASE: u32 = 0x10;
+
+/// Bit of a redirection entry making the interrupt active-low.
+const ACTIVE_LOW: u32 = 1 << 13;
+/// Bit of a redirection entry making the interrupt level-triggered.
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+/// Bit of a redirection entry masking the interrupt.
+const MASKED: u32 = 1 << 16;
+
+/// Interrupt override polarity flags, in the MADT.
+const OVERRIDE_ACTIVE_LOW: u16 = 0b11;
+/// Interrupt override trigger mode flags, in the MADT.
+const OVERRIDE_LEVEL_TRIGGERED: u16 = 0b11 << 2;
+
+/// A single I/O APIC.
+struct IoApic {
+    addr: VirtAddr,
+    /// The first global system interrupt handled by this I/O APIC.
+    gsi_base: u32,
+    /// The number of global system interrupts handled by this I/O APIC.
+    gsi_count: u32,
+}
+
+impl IoApic {
+    fn read(&mut self, register: u32) -> u32 {
+        // Safety: The registers are mapped in `init`, and the `&mut self` prevents another
+        // register being selected in between
+        unsafe {
+            ptr::write_volatile(self.addr.as_mut_ptr::<u32>(), register);
+            ptr::read_volatile((self.addr + 0x10).as_ptr::<u32>())
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        // Safety: As with `read`
+        unsafe {
+            ptr::write_volatile(self.addr.as_mut_ptr::<u32>(), register);
+            ptr::write_volatile((self.addr + 0x10).as_mut_ptr::<u32>(), value);
+        }
+    }
+
+    /// Set the redirection entry for global system interrupt `gsi`.
+    fn set_redirection(&mut self, gsi: u32, low: u32, destination: u32) {
+        let register = REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+        self.write(register + 1, destination << 24);
+        self.write(register, low);
+    }
+
+    fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let register = REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+        let low = self.read(register);
+        self.write(register, if masked { low | MASKED } else { low & !MASKED });
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.gsi_count).contains(&gsi)
+    }
+}
+
+static IO_APICS: Once<Vec<Mutex<IoApic>>> = Once::new();
+/// The global system interrupt each ISA IRQ is routed to, if any.
+static ISA_ROUTES: Once<[Option<u32>; IRQ_COUNT as usize]> = Once::new();
+
+/// Find the I/O APIC handling global system interrupt `gsi`.
+fn io_apic_for(gsi: u32) -> Option<&'static Mutex<IoApic>> {
+    IO_APICS
+        .get()?
+        .iter()
+        .find(|io_apic| io_apic.lock().handles(gsi))
+}
+
+/// Map the I/O APICs described by the MADT, and route the ISA IRQs to vectors `IRQ_BASE..` on
+/// the local APIC with ID `destination`, masked.
+pub(super) fn init(madt: &Madt, destination: u32) {
+    let io_apics = madt
+        .entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::IoApic { addr, gsi_base, .. } => {
+                let addr = unsafe {
+                    crate::memory::controller().identity_map(
+                        addr..addr + Size4K::SIZE,
+                        EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXEC,
+                    )
+                };
+                let mut io_apic = IoApic {
+                    addr,
+                    gsi_base,
+                    gsi_count: 0,
+                };
+                io_apic.gsi_count = (io_apic.read(REG_VERSION) >> 16 & 0xff) + 1;
+                Some(Mutex::new(io_apic))
+            }
+            _ => None,
+        })
+        .collect();
+    IO_APICS.call_once(|| io_apics);
+
+    let mut routes = [None; IRQ_COUNT as usize];
+    for (irq, route) in routes.iter_mut().enumerate() {
+        let overrides = || {
+            madt.entries().filter_map(|entry| match entry {
+                MadtEntry::InterruptOverride { irq, gsi, flags } => Some((irq, gsi, flags)),
+                _ => None,
+            })
+        };
+        // ISA IRQs are identity mapped, active-high, and edge-triggered unless overridden
+        let (gsi, flags) = match overrides().find(|&(source, ..)| source as usize == irq) {
+            Some((_, gsi, flags)) => (gsi, flags),
+            // Another IRQ has been routed to this one's GSI (E.G. the PIT is usually moved from
+            // IRQ 0 to GSI 2, taking the cascade's place)
+            None if overrides().any(|(_, gsi, _)| gsi as usize == irq) => continue,
+            None => (irq as u32, 0),
+        };
+
+        let mut low = MASKED | (IRQ_BASE as u32 + irq as u32);
+        if flags & OVERRIDE_ACTIVE_LOW == OVERRIDE_ACTIVE_LOW {
+            low |= ACTIVE_LOW;
+        }
+        if flags & OVERRIDE_LEVEL_TRIGGERED == OVERRIDE_LEVEL_TRIGGERED {
+            low |= LEVEL_TRIGGERED;
+        }
+        if let Some(io_apic) = io_apic_for(gsi) {
+            io_apic.lock().set_redirection(gsi, low, destination);
+            *route = Some(gsi);
+        }
+    }
+    ISA_ROUTES.call_once(|| routes);
+}
+
+/// Mask or unmask ISA IRQ `irq`. Does nothing if the IRQ isn't routed to an I/O APIC.
+pub(super) fn set_masked(irq: u8, masked: bool) {
+    let route = ISA_ROUTES.get().expect("I/O APIC not initialised")[irq as usize];
+    if let Some(gsi) = route {
+        if let Some(io_apic) = io_apic_for(gsi) {
+            io_apic.lock().set_masked(gsi, masked);
+        }
+    }
+}
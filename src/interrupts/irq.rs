@@ -0,0 +1,133 @@
+//! Dispatching of hardware IRQs to the handlers registered by drivers.
+//!
+//! IRQs are the legacy ISA IRQ numbers, whether they are raised through the 8259 PICs or routed
+//! through the I/O APIC.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame};
+
+use super::{
+    apic, ioapic,
+    pic::{self, IRQ_COUNT},
+};
+
+/// A function handling an IRQ. It is called with interrupts disabled, and the IRQ is acknowledged
+/// once it returns.
+pub type IrqHandler = fn(irq: u8);
+
+static HANDLERS: Mutex<[Option<IrqHandler>; IRQ_COUNT as usize]> =
+    Mutex::new([None; IRQ_COUNT as usize]);
+
+/// Whether IRQs are routed through the APICs, rather than the 8259 PICs.
+static USING_APIC: AtomicBool = AtomicBool::new(false);
+
+/// Mask or unmask `irq` on whichever interrupt controller is in use.
+fn set_masked(irq: u8, masked: bool) {
+    if USING_APIC.load(Ordering::Acquire) {
+        ioapic::set_masked(irq, masked);
+    } else {
+        pic::set_masked(irq, masked);
+    }
+}
+
+/// Switch from the 8259 PICs to the APICs, which must already be set up, unmasking the IRQs
+/// that have handlers. The PICs are disabled if `has_8259` is set.
+pub(super) fn use_apic(has_8259: bool) {
+    interrupts::without_interrupts(|| {
+        if has_8259 {
+            pic::disable();
+        }
+        USING_APIC.store(true, Ordering::Release);
+        let handlers = HANDLERS.lock();
+        for (irq, handler) in handlers.iter().enumerate() {
+            if handler.is_some() {
+                ioapic::set_masked(irq as u8, false);
+            }
+        }
+    });
+}
+
+/// Attach `handler` to `irq` and unmask it, returning the handler it replaced.
+///
+/// # Panics
+///
+/// * If `irq` isn't a valid IRQ line (0-15).
+#[allow(dead_code)]
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Option<IrqHandler> {
+    assert!(irq < IRQ_COUNT, "Invalid IRQ {}", irq);
+    // Interrupts are disabled so that the IRQ can't fire while the handlers are locked
+    interrupts::without_interrupts(|| {
+        let old = HANDLERS.lock()[irq as usize].replace(handler);
+        set_masked(irq, false);
+        old
+    })
+}
+
+/// Detach the handler from `irq` and mask it, returning the handler.
+///
+/// # Panics
+///
+/// * If `irq` isn't a valid IRQ line (0-15).
+#[allow(dead_code)]
+pub fn unregister_irq(irq: u8) -> Option<IrqHandler> {
+    assert!(irq < IRQ_COUNT, "Invalid IRQ {}", irq);
+    interrupts::without_interrupts(|| {
+        set_masked(irq, true);
+        HANDLERS.lock()[irq as usize].take()
+    })
+}
+
+/// Common body of the IRQ handlers.
+fn dispatch(irq: u8) {
+    let using_apic = USING_APIC.load(Ordering::Acquire);
+    if !using_apic && pic::is_spurious(irq) {
+        return;
+    }
+
+    // Copy the handler out, so the lock isn't held while it runs
+    let handler = HANDLERS.lock()[irq as usize];
+    if let Some(handler) = handler {
+        handler(irq);
+    }
+
+    match apic::local_apic() {
+        Some(lapic) if using_apic => lapic.end_of_interrupt(),
+        _ => pic::end_of_interrupt(irq),
+    }
+}
+
+/// Define the IDT handlers for the given IRQs, and an array of them indexed by IRQ.
+macro_rules! irq_handlers {
+    ($($irq:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_: InterruptStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        /// The IDT handler for each IRQ.
+        pub(super) static IRQ_HANDLERS: [extern "x86-interrupt" fn(InterruptStackFrame);
+            IRQ_COUNT as usize] = [$($name),*];
+    };
+}
+
+irq_handlers! {
+    0 => irq0_handler,
+    1 => irq1_handler,
+    2 => irq2_handler,
+    3 => irq3_handler,
+    4 => irq4_handler,
+    5 => irq5_handler,
+    6 => irq6_handler,
+    7 => irq7_handler,
+    8 => irq8_handler,
+    9 => irq9_handler,
+    10 => irq10_handler,
+    11 => irq11_handler,
+    12 => irq12_handler,
+    13 => irq13_handler,
+    14 => irq14_handler,
+    15 => irq15_handler,
+}
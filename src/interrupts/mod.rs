@@ -1,34 +1,81 @@
+pub mod apic;
+pub mod exceptions;
+mod ioapic;
+pub mod irq;
+pub mod page_fault;
+pub mod pic;
+
 use spin::Lazy;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::{
+    structures::idt::{Entry, EntryOptions, InterruptDescriptorTable},
+    VirtAddr,
+};
 
-use crate::{gdt::tss, println};
+use crate::{gdt::tss, time::clock};
 
 static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    use exceptions::*;
+
     let mut idt = InterruptDescriptorTable::new();
 
+    // Safety: Each entry point matches whether its exception pushes an error code
     unsafe {
-        idt.double_fault
-            .set_handler_fn(double_fault_handler)
+        set_entry(&mut idt.divide_error, divide_error_handler);
+        set_entry(&mut idt.debug, debug_handler);
+        set_entry(&mut idt.non_maskable_interrupt, nmi_handler).set_stack_index(tss::NMI_IST_INDEX);
+        set_entry(&mut idt.breakpoint, breakpoint_handler);
+        set_entry(&mut idt.overflow, overflow_handler);
+        set_entry(&mut idt.bound_range_exceeded, bound_range_exceeded_handler);
+        set_entry(&mut idt.invalid_opcode, invalid_opcode_handler);
+        set_entry(&mut idt.device_not_available, device_not_available_handler);
+        set_entry(&mut idt.double_fault, double_fault_handler)
             .set_stack_index(tss::DOUBLE_FAULT_IST_INDEX);
+        set_entry(&mut idt.invalid_tss, invalid_tss_handler);
+        set_entry(&mut idt.segment_not_present, segment_not_present_handler);
+        set_entry(&mut idt.stack_segment_fault, stack_segment_fault_handler);
+        set_entry(
+            &mut idt.general_protection_fault,
+            general_protection_fault_handler,
+        );
+        set_entry(&mut idt.page_fault, page_fault::page_fault_handler);
+        set_entry(&mut idt.x87_floating_point, x87_floating_point_handler);
+        set_entry(&mut idt.alignment_check, alignment_check_handler);
+        set_entry(&mut idt.machine_check, machine_check_handler)
+            .set_stack_index(tss::MACHINE_CHECK_IST_INDEX);
+        set_entry(&mut idt.simd_floating_point, simd_floating_point_handler);
+        set_entry(&mut idt.virtualization, virtualization_handler);
+        set_entry(
+            &mut idt.vmm_communication_exception,
+            vmm_communication_handler,
+        );
+        set_entry(&mut idt.security_exception, security_handler);
+    }
+
+    for (irq, &handler) in irq::IRQ_HANDLERS.iter().enumerate() {
+        idt[pic::IRQ_BASE as usize + irq].set_handler_fn(handler);
     }
-    idt.breakpoint.set_handler_fn(breakpoint_handler);
-    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt[pic::DISABLED_IRQ_BASE as usize + 7].set_handler_fn(pic::disabled_spurious_handler);
+    idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(apic::spurious_handler);
+    idt[clock::TIMER_VECTOR as usize].set_handler_fn(clock::timer_interrupt_handler);
 
     idt
 });
 
-pub fn init() {
-    IDT.load();
-}
-
-extern "x86-interrupt" fn double_fault_handler(info: InterruptStackFrame, _: u64) -> ! {
-    panic!("Double fault exception\n{:#?}", info);
-}
-
-extern "x86-interrupt" fn breakpoint_handler(info: InterruptStackFrame) {
-    println!("Breakpoint: {:#?}", info);
+/// Install an exception entry point defined with `entry_point!`.
+///
+/// # Safety
+///
+/// The entry point must have been defined with the `error_code` argument if, and only if, the
+/// exception pushes an error code.
+unsafe fn set_entry<F>(entry: &mut Entry<F>, handler: extern "C" fn()) -> &mut EntryOptions {
+    // Safety: The entry point saves the registers and returns with `iretq`, like an
+    // `x86-interrupt` function
+    unsafe { entry.set_handler_addr(VirtAddr::new(handler as usize as u64)) }
 }
 
-extern "x86-interrupt" fn divide_error_handler(info: InterruptStackFrame) {
-    panic!("Division by 0\n{:#?}", info);
+/// Load the IDT, set up the PICs, and enable interrupts.
+pub fn init() {
+    IDT.load();
+    pic::init();
+    x86_64::instructions::interrupts::enable();
 }
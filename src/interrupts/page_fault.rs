@@ -0,0 +1,102 @@
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptStackFrame, PageFaultErrorCode},
+};
+
+use super::exceptions::{entry_point, GeneralRegisters};
+use crate::{
+    backtrace::Symbolized,
+    gdt::tss,
+    memory::{paging::TableWalk, VirtAddr},
+    println,
+};
+
+/// A function that tries to resolve a page fault, E.G. by mapping the faulting page. It returns
+/// `true` if the fault was resolved, in which case the faulting instruction is retried.
+pub type PageFaultResolver = fn(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool;
+
+static RESOLVER: Mutex<Option<PageFaultResolver>> = Mutex::new(None);
+
+/// Set the function that page faults are routed to before they are reported as fatal, returning
+/// the previous one (so that it can be called as a fallback).
+#[allow(dead_code)]
+pub fn set_page_fault_resolver(resolver: PageFaultResolver) -> Option<PageFaultResolver> {
+    RESOLVER.lock().replace(resolver)
+}
+
+entry_point!(
+    page_fault_handler,
+    error_code,
+    |general, frame, error_code| {
+        handle(
+            frame,
+            PageFaultErrorCode::from_bits_truncate(error_code),
+            general,
+        )
+    }
+);
+
+fn handle(
+    frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+    general: &GeneralRegisters,
+) {
+    let addr = VirtAddr::new(Cr2::read().as_u64() as usize);
+
+    // Copy the resolver out, so the lock isn't held while it runs
+    let resolver = *RESOLVER.lock();
+    if let Some(resolver) = resolver {
+        if resolver(addr, error_code) {
+            return;
+        }
+    }
+
+    println!(
+        "Page fault accessing {}: {}",
+        addr,
+        DecodedErrorCode(error_code)
+    );
+    println!("{}", TableWalk::new(addr));
+    println!("{}", general);
+    println!(
+        "Faulting instruction: {}",
+        Symbolized(frame.instruction_pointer.as_u64() as usize)
+    );
+    if let Some(stack) = tss::ist_guard_page(addr) {
+        panic!("IST stack overflow ({} stack)\n{:#?}", stack, frame);
+    }
+    panic!("Unhandled page fault\n{:#?}", frame);
+}
+
+/// Human-readable description of a [`PageFaultErrorCode`].
+struct DecodedErrorCode(PageFaultErrorCode);
+
+impl fmt::Display for DecodedErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+        f.write_str(if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "protection violation"
+        } else {
+            "page not present"
+        })?;
+        f.write_str(if code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            ", instruction fetch"
+        } else if code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            ", write"
+        } else {
+            ", read"
+        })?;
+        f.write_str(if code.contains(PageFaultErrorCode::USER_MODE) {
+            " from user mode"
+        } else {
+            " from kernel mode"
+        })?;
+        if code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            f.write_str(", reserved bit set in a page table entry")?;
+        }
+        write!(f, " ({:?})", code)
+    }
+}
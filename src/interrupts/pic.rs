@@ -0,0 +1,171 @@
+//! Driver for the pair of legacy 8259 programmable interrupt controllers.
+
+use spin::Mutex;
+use x86_64::{instructions::port::Port, structures::idt::InterruptStackFrame};
+
+/// Vector that IRQ 0 is remapped to. The master PIC handles vectors `IRQ_BASE..IRQ_BASE + 8`, and
+/// the slave PIC the 8 vectors after that.
+pub const IRQ_BASE: u8 = 32;
+/// Number of IRQ lines on the two PICs.
+pub const IRQ_COUNT: u8 = 16;
+/// Vector that IRQ 0 is remapped to once the PICs are disabled, so spurious IRQs they may still
+/// raise can't be mistaken for IRQs from the I/O APIC.
+pub const DISABLED_IRQ_BASE: u8 = 0xe0;
+/// The master IRQ line that the slave PIC is cascaded through.
+const CASCADE_IRQ: u8 = 2;
+
+/// Initialisation command: start initialisation, expect ICW4.
+const ICW1_INIT: u8 = 0x11;
+/// ICW4: 8086/88 mode.
+const ICW4_8086: u8 = 0x01;
+/// OCW2: non-specific end of interrupt.
+const CMD_EOI: u8 = 0x20;
+/// OCW3: read the in-service register on the next read of the command port.
+const CMD_READ_ISR: u8 = 0x0b;
+
+/// A single 8259 PIC.
+struct Pic {
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    const fn new(base: u16) -> Self {
+        Self {
+            command: Port::new(base),
+            data: Port::new(base + 1),
+        }
+    }
+
+    /// Read the in-service register, which has a bit set for each IRQ currently being handled.
+    fn in_service(&mut self) -> u8 {
+        unsafe {
+            self.command.write(CMD_READ_ISR);
+            self.command.read()
+        }
+    }
+
+    fn end_of_interrupt(&mut self) {
+        unsafe { self.command.write(CMD_EOI) };
+    }
+}
+
+/// The master and slave PICs, chained through [`CASCADE_IRQ`].
+struct ChainedPics {
+    master: Pic,
+    slave: Pic,
+}
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics {
+    master: Pic::new(0x20),
+    slave: Pic::new(0xa0),
+});
+
+/// Wait a tiny amount of time, for the PICs to process a command on older hardware. This writes
+/// to an unused port, which takes long enough.
+fn io_wait() {
+    unsafe { Port::<u8>::new(0x80).write(0) };
+}
+
+/// Remap the PICs so IRQ 0 is raised at vector `base`, and mask every IRQ except the cascade.
+fn remap(base: u8) {
+    let mut pics = PICS.lock();
+    let ChainedPics { master, slave } = &mut *pics;
+    unsafe {
+        master.command.write(ICW1_INIT);
+        io_wait();
+        slave.command.write(ICW1_INIT);
+        io_wait();
+        // ICW2: Vector offsets
+        master.data.write(base);
+        io_wait();
+        slave.data.write(base + 8);
+        io_wait();
+        // ICW3: Tell the master which line the slave is on, and the slave its cascade identity
+        master.data.write(1 << CASCADE_IRQ);
+        io_wait();
+        slave.data.write(CASCADE_IRQ);
+        io_wait();
+        master.data.write(ICW4_8086);
+        io_wait();
+        slave.data.write(ICW4_8086);
+        io_wait();
+
+        master.data.write(!(1 << CASCADE_IRQ));
+        slave.data.write(0xff);
+    }
+}
+
+/// Remap the PICs to vectors [`IRQ_BASE`] to `IRQ_BASE + 15`, so they don't collide with CPU
+/// exceptions, and mask every IRQ except the cascade.
+pub fn init() {
+    remap(IRQ_BASE);
+}
+
+/// Mask or unmask `irq`.
+///
+/// # Panics
+///
+/// * If `irq` isn't a valid IRQ line (0-15).
+pub fn set_masked(irq: u8, masked: bool) {
+    assert!(irq < IRQ_COUNT, "Invalid IRQ {}", irq);
+    let mut pics = PICS.lock();
+    let (pic, line) = if irq < 8 {
+        (&mut pics.master, irq)
+    } else {
+        (&mut pics.slave, irq - 8)
+    };
+    unsafe {
+        let mask = pic.data.read();
+        pic.data.write(if masked {
+            mask | 1 << line
+        } else {
+            mask & !(1 << line)
+        });
+    }
+}
+
+/// Mask every IRQ, and move the PICs out of the way to [`DISABLED_IRQ_BASE`], when switching to
+/// the APICs.
+pub fn disable() {
+    remap(DISABLED_IRQ_BASE);
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.master.data.write(0xff);
+        pics.slave.data.write(0xff);
+    }
+}
+
+/// Handler for the spurious IRQ 7 the master PIC can raise after being disabled. With the cascade
+/// masked, the slave can't raise any.
+pub(super) extern "x86-interrupt" fn disabled_spurious_handler(_: InterruptStackFrame) {}
+
+/// Returns whether `irq` is spurious, in which case it must not be acknowledged with
+/// [`end_of_interrupt`].
+///
+/// The PICs raise IRQ 7 (or 15 on the slave) if an IRQ goes away before the CPU acknowledges it.
+/// These are detected by checking that the IRQ is really in service. A spurious IRQ 15 is still
+/// acknowledged on the master, which doesn't know it was spurious.
+pub fn is_spurious(irq: u8) -> bool {
+    let mut pics = PICS.lock();
+    match irq {
+        7 => pics.master.in_service() & 1 << 7 == 0,
+        15 => {
+            let spurious = pics.slave.in_service() & 1 << 7 == 0;
+            if spurious {
+                pics.master.end_of_interrupt();
+            }
+            spurious
+        }
+        _ => false,
+    }
+}
+
+/// Signal the end of the handler for `irq`, allowing the PICs to raise it again.
+pub fn end_of_interrupt(irq: u8) {
+    let mut pics = PICS.lock();
+    if irq >= 8 {
+        pics.slave.end_of_interrupt();
+    }
+    pics.master.end_of_interrupt();
+}
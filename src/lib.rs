@@ -1,22 +1,49 @@
 #![no_std]
+#![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
 #![feature(slice_index_methods)]
+#![test_runner(crate::testing::runner)]
+#![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+mod acpi;
+mod backtrace;
+mod dmesg;
+mod gdb;
 mod gdt;
 mod init;
+mod input;
 mod interrupts;
+mod logger;
 mod memory;
 mod output;
+mod ring_buffer;
+mod shell;
+#[cfg(test)]
+mod testing;
+mod time;
 
 use core::panic::PanicInfo;
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("Kernel {}", info);
+    backtrace::print_backtrace();
+    dmesg::flush_to_serial();
 
     hlt_loop();
 }
 
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panic(info);
+}
+
 pub(crate) fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
@@ -0,0 +1,179 @@
+//! A logger for the `log` crate, writing timestamped messages to the VGA console (coloured by
+//! level), the first serial port, and the kernel message buffer.
+//!
+//! Which messages are logged is set by the `log=` kernel command line option, a comma separated
+//! list of either a default level, or a module and the level for it and its submodules, E.G.
+//! `log=warn,memory::paging=trace,acpi=off`. Modules are given relative to the crate root, and the
+//! most specific filter matching a module wins.
+
+use core::fmt::Write;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Once;
+
+use crate::{
+    dmesg::MessageBuilder,
+    output::{
+        serial,
+        vga::{self, Color},
+    },
+};
+
+/// The level logged at when the command line doesn't say otherwise.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+/// Maximum number of module filters that can be given on the command line.
+const MAX_FILTERS: usize = 16;
+/// The prefix of the module paths of this crate.
+const CRATE_PREFIX: &str = concat!(env!("CARGO_PKG_NAME"), "::");
+
+/// The minimum level of the messages logged from a module and its submodules.
+#[derive(Debug, Clone, Copy)]
+struct Filter {
+    module: &'static str,
+    level: LevelFilter,
+}
+
+#[derive(Debug)]
+struct Filters {
+    default: LevelFilter,
+    modules: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    /// Parse the value of the `log=` command line option. Invalid filters are ignored.
+    fn parse(spec: &'static str) -> Self {
+        let mut filters = Self {
+            default: DEFAULT_LEVEL,
+            modules: [None; MAX_FILTERS],
+        };
+        let mut slots = filters.modules.iter_mut();
+        for (module, level) in spec.split(',').filter_map(parse_filter) {
+            match module {
+                Some(module) => match slots.next() {
+                    Some(slot) => *slot = Some(Filter { module, level }),
+                    None => break,
+                },
+                None => filters.default = level,
+            }
+        }
+        filters
+    }
+
+    /// The minimum level of messages logged from the module `target`.
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = strip_crate_prefix(target);
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|filter| {
+                target
+                    .strip_prefix(filter.module)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|filter| filter.module.len())
+            .map_or(self.default, |filter| filter.level)
+    }
+
+    /// The most verbose level of any filter.
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .map(|filter| filter.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Parse a single filter, either a level or `module=level`.
+fn parse_filter(filter: &'static str) -> Option<(Option<&'static str>, LevelFilter)> {
+    match filter.split_once('=') {
+        Some((module, level)) => Some((Some(strip_crate_prefix(module)), level.parse().ok()?)),
+        None => Some((None, filter.parse().ok()?)),
+    }
+}
+
+/// Remove the crate name from the start of a module path.
+fn strip_crate_prefix(module: &str) -> &str {
+    module.strip_prefix(CRATE_PREFIX).unwrap_or(module)
+}
+
+/// The colour of a level's name on the VGA console.
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::LightRed,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::LightGreen,
+        Level::Debug => Color::LightCyan,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+static FILTERS: Once<Filters> = Once::new();
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTERS.get().map_or(DEFAULT_LEVEL, |filters| {
+            filters.level_for(metadata.target())
+        }) >= metadata.level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let nanos = crate::time::clock::monotonic_nanos();
+        let (secs, micros) = (nanos / 1_000_000_000, nanos / 1000 % 1_000_000);
+        let target = strip_crate_prefix(record.target());
+
+        let mut message = MessageBuilder::new();
+        let _ = write!(
+            message,
+            "[{:5}.{:06}] {:5} {}: {}",
+            secs,
+            micros,
+            record.level(),
+            target,
+            record.args()
+        );
+        message.finish();
+
+        vga::_print(format_args!("[{:5}.{:06}] ", secs, micros));
+        vga::_print_colored(
+            level_color(record.level()),
+            format_args!("{:5}", record.level()),
+        );
+        vga::_print(format_args!(" {}: {}\n", target, record.args()));
+        serial::_print(format_args!(
+            "[{:5}.{:06}] {:5} {}: {}\n",
+            secs,
+            micros,
+            record.level(),
+            target,
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Start logging, with the filters from the `log=` option in the kernel command line `cmdline`.
+pub fn init(cmdline: &'static str) {
+    let spec = cmdline
+        .split_whitespace()
+        .find_map(|option| option.strip_prefix("log="))
+        .unwrap_or("");
+    let filters = FILTERS.call_once(|| Filters::parse(spec));
+
+    log::set_logger(&LOGGER).expect("Logger already set");
+    log::set_max_level(filters.max_level());
+
+    for filter in spec.split(',').filter(|filter| !filter.is_empty()) {
+        if parse_filter(filter).is_none() {
+            log::warn!("Ignoring invalid log filter {:?}", filter);
+        }
+    }
+}
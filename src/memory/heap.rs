@@ -0,0 +1,111 @@
+//! The kernel heap, which backs the `alloc` crate (`Box`, `Vec`, `String`, etc).
+//!
+//! Allocations small enough for one of the slab allocator's size classes are served from it, so
+//! the many small objects the kernel allocates don't fragment the heap. Everything else, and
+//! anything allocated before the slab allocator can map slabs, comes from a linked list allocator.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+
+use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts;
+
+use super::{
+    paging::{ActivePageTable, EntryFlags, Page},
+    phys::FrameAllocator,
+    slab, Size4K, SizedRegion, VirtAddr,
+};
+use crate::println;
+
+/// Start of the virtual address range reserved for the kernel heap (the first address mapped by
+/// the second P4 entry).
+pub const HEAP_START: usize = 0x0000_0080_0000_0000;
+/// Size of the kernel heap, in bytes.
+pub const HEAP_SIZE: usize = 1024 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// The linked list allocator, for allocations the slab allocator doesn't serve.
+static HEAP: LockedHeap = LockedHeap::empty();
+
+/// The global allocator, which tries the slab allocator before the linked list allocator.
+struct KernelAllocator;
+
+// Interrupts are disabled while the allocators are locked, so an interrupt handler that allocates
+// can't deadlock with the code it interrupted
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| match slab::allocate(layout) {
+            Some(ptr) => ptr.as_ptr(),
+            None => HEAP.alloc(layout),
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| {
+            // Objects that could have been allocated from a slab may still have come from the
+            // heap, if there was no slab to put them in
+            if slab::contains(ptr) {
+                slab::deallocate(NonNull::new_unchecked(ptr), layout);
+            } else {
+                HEAP.dealloc(ptr, layout);
+            }
+        })
+    }
+}
+
+/// Map the whole heap range to newly allocated frames, and hand it to the global allocator.
+pub fn init<A>(active_table: &mut ActivePageTable, allocator: &mut A)
+where
+    A: FrameAllocator<Size4K>,
+{
+    let mut addr = VirtAddr::new(HEAP_START);
+    while addr < VirtAddr::new(HEAP_START + HEAP_SIZE) {
+        active_table.map(
+            Page::<Size4K>::containing_addr(addr),
+            EntryFlags::WRITABLE | EntryFlags::NO_EXEC,
+            allocator,
+        );
+        addr += Size4K::SIZE;
+    }
+
+    // Safety: The heap range was just mapped, and isn't used for anything else.
+    unsafe { HEAP.lock().init(HEAP_START, HEAP_SIZE) };
+}
+
+/// Usage statistics for the linked list part of the kernel heap. See [`slab::print_stats`] for
+/// the rest.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Total size of the heap, in bytes.
+    pub size: usize,
+    /// Number of bytes currently allocated.
+    pub used: usize,
+    /// Number of bytes available for allocation (possibly fragmented).
+    pub free: usize,
+}
+
+/// Returns the current usage statistics of the kernel heap.
+pub fn stats() -> HeapStats {
+    interrupts::without_interrupts(|| {
+        let heap = HEAP.lock();
+        HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+        }
+    })
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    let stats = stats();
+    println!(
+        "Kernel heap: {} bytes used, {} bytes free, {} bytes total",
+        stats.used, stats.free, stats.size
+    );
+    panic!("Heap allocation of {:?} failed", layout);
+}
@@ -1,35 +1,84 @@
-mod addr;
-pub use addr::Addr;
+pub use rux_memory::{PhysAddr, Size1G, Size2M, Size4K, SizedRegion, VirtAddr};
+pub mod heap;
 pub mod paging;
 pub mod phys;
+pub mod slab;
 
-/// Specifies the size of a region (page or frame) of memory.
-/// Implemented by [`Size4K`], [`Size2M`], and [`Size1G`].
-pub trait SizedRegion {
-    /// The size (in bytes) of the memory region
-    const SIZE: usize;
-    /// A human-readable representation of the size
-    const DISPLAY: &'static str;
-}
+use core::ops::Range;
+
+use spin::{Mutex, MutexGuard, Once};
 
-/// A 4 kibibyte memory Size (page or frame).
-pub enum Size4K {}
-/// A 2 mebibyte memory Size (page or frame).
-pub enum Size2M {}
-/// A 1 gibibyte memory Size (page or frame).
-pub enum Size1G {}
+use paging::{ActivePageTable, EntryFlags};
 
-impl SizedRegion for Size4K {
-    const SIZE: usize = 4 * 1024;
-    const DISPLAY: &'static str = "4K";
+/// The frame allocator used by the kernel, selected by the `frame_alloc_*` features.
+#[cfg(feature = "frame_alloc_buddy")]
+pub type KernelFrameAllocator = phys::BuddyFrameAllocator;
+/// The frame allocator used by the kernel, selected by the `frame_alloc_*` features.
+#[cfg(all(feature = "frame_alloc_bitmap", not(feature = "frame_alloc_buddy")))]
+pub type KernelFrameAllocator = phys::BitmapFrameAllocator;
+/// The frame allocator used by the kernel, selected by the `frame_alloc_*` features.
+#[cfg(not(any(feature = "frame_alloc_bitmap", feature = "frame_alloc_buddy")))]
+pub type KernelFrameAllocator = phys::SimpleFrameAllocator<'static>;
+
+/// The kernel's page tables and frame allocator, for use once memory management is set up.
+pub struct MemoryController {
+    pub active_table: ActivePageTable,
+    pub frame_allocator: KernelFrameAllocator,
 }
 
-impl SizedRegion for Size2M {
-    const SIZE: usize = 2 * 1024 * 1024;
-    const DISPLAY: &'static str = "2M";
+impl MemoryController {
+    /// Identity map the physical memory in `range`, E.G. for memory-mapped hardware or firmware
+    /// tables, returning the virtual address of its start. Pages that are already mapped are left
+    /// as they are.
+    ///
+    /// # Safety
+    ///
+    /// The frame allocator must never hand out frames in `range`, I.E. they must not be marked as
+    /// available in the memory map.
+    ///
+    /// # Panics
+    ///
+    /// * If `range` extends past the lower half of the virtual address space.
+    #[allow(dead_code)]
+    pub unsafe fn identity_map(&mut self, range: Range<PhysAddr>, flags: EntryFlags) -> VirtAddr {
+        let start = VirtAddr::new(range.start.as_usize());
+        paging::identity_map_range(
+            &mut self.active_table,
+            range,
+            flags,
+            &mut self.frame_allocator,
+        );
+        start
+    }
+}
+
+static MEMORY_CONTROLLER: Once<Mutex<MemoryController>> = Once::new();
+
+/// Set up memory management: create the frame allocator, remap the kernel with the permissions
+/// of each of its ELF sections, map the interrupt stacks, and set up the kernel heap.
+pub fn init(mb: &'static multiboot2::BootInformation) {
+    let mut frame_allocator = KernelFrameAllocator::new(mb);
+    let mut active_table = paging::remap_the_kernel(&mut frame_allocator, mb);
+    crate::gdt::tss::map_ist_stacks(&mut active_table, &mut frame_allocator);
+    heap::init(&mut active_table, &mut frame_allocator);
+
+    MEMORY_CONTROLLER.call_once(|| {
+        Mutex::new(MemoryController {
+            active_table,
+            frame_allocator,
+        })
+    });
 }
 
-impl SizedRegion for Size1G {
-    const SIZE: usize = 1 * 1024 * 1024 * 1024;
-    const DISPLAY: &'static str = "1G";
+/// Lock the kernel's [`MemoryController`].
+///
+/// # Panics
+///
+/// * If memory management hasn't been set up with [`init`] yet.
+#[allow(dead_code)]
+pub fn controller() -> MutexGuard<'static, MemoryController> {
+    MEMORY_CONTROLLER
+        .get()
+        .expect("Memory management not initialised")
+        .lock()
 }
@@ -0,0 +1,290 @@
+use core::ptr::NonNull;
+
+use x86_64::instructions::tlb;
+
+use super::{
+    table::P4, Entry, EntryFlags, Level1, Level2, Level3, Level4, Page, PageTable, PageTableLevel,
+};
+use crate::memory::{
+    phys::{AllocatedFrame, FrameAllocator},
+    PhysAddr, Size1G, Size2M, Size4K, SizedRegion, VirtAddr,
+};
+
+/// A page size that can be mapped by a [`Mapper`]. Implemented by [`Size4K`], [`Size2M`], and
+/// [`Size1G`].
+pub trait PageSize: SizedRegion + Sized {
+    /// Level of the page table whose entries map pages of this size.
+    type Level: PageTableLevel;
+    /// Flags that must be set in an entry mapping a page of this size.
+    const FLAGS: EntryFlags;
+
+    /// Get the entry that maps `page`, if all of the page tables above it exist.
+    fn entry<'a>(p4: &'a PageTable<Level4>, page: &Page<Self>) -> Option<&'a Entry<Self::Level>>;
+
+    /// Get the entry that maps `page` mutably, if all of the page tables above it exist.
+    fn entry_mut<'a>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+    ) -> Option<&'a mut Entry<Self::Level>>;
+
+    /// Get the entry that maps `page`, creating any page tables above it that don't exist yet.
+    fn entry_create<'a, A>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+        allocator: &mut A,
+    ) -> &'a mut Entry<Self::Level>
+    where
+        A: FrameAllocator<Size4K>;
+}
+
+impl PageSize for Size4K {
+    type Level = Level1;
+    const FLAGS: EntryFlags = EntryFlags::empty();
+
+    fn entry<'a>(p4: &'a PageTable<Level4>, page: &Page<Self>) -> Option<&'a Entry<Level1>> {
+        let p1 = p4
+            .next_table(page.p4_index())?
+            .next_table(page.p3_index())?
+            .next_table(page.p2_index())?;
+        Some(&p1[page.p1_index()])
+    }
+
+    fn entry_mut<'a>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+    ) -> Option<&'a mut Entry<Level1>> {
+        let p1 = p4
+            .next_table_mut(page.p4_index())?
+            .next_table_mut(page.p3_index())?
+            .next_table_mut(page.p2_index())?;
+        Some(&mut p1[page.p1_index()])
+    }
+
+    fn entry_create<'a, A>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+        allocator: &mut A,
+    ) -> &'a mut Entry<Level1>
+    where
+        A: FrameAllocator<Size4K>,
+    {
+        let p1 = p4
+            .next_table_create(page.p4_index(), allocator)
+            .next_table_create(page.p3_index(), allocator)
+            .next_table_create(page.p2_index(), allocator);
+        &mut p1[page.p1_index()]
+    }
+}
+
+impl PageSize for Size2M {
+    type Level = Level2;
+    const FLAGS: EntryFlags = EntryFlags::HUGE;
+
+    fn entry<'a>(p4: &'a PageTable<Level4>, page: &Page<Self>) -> Option<&'a Entry<Level2>> {
+        let p2 = p4
+            .next_table(page.p4_index())?
+            .next_table(page.p3_index())?;
+        Some(&p2[page.p2_index()])
+    }
+
+    fn entry_mut<'a>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+    ) -> Option<&'a mut Entry<Level2>> {
+        let p2 = p4
+            .next_table_mut(page.p4_index())?
+            .next_table_mut(page.p3_index())?;
+        Some(&mut p2[page.p2_index()])
+    }
+
+    fn entry_create<'a, A>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+        allocator: &mut A,
+    ) -> &'a mut Entry<Level2>
+    where
+        A: FrameAllocator<Size4K>,
+    {
+        let p2 = p4
+            .next_table_create(page.p4_index(), allocator)
+            .next_table_create(page.p3_index(), allocator);
+        &mut p2[page.p2_index()]
+    }
+}
+
+impl PageSize for Size1G {
+    type Level = Level3;
+    const FLAGS: EntryFlags = EntryFlags::HUGE;
+
+    fn entry<'a>(p4: &'a PageTable<Level4>, page: &Page<Self>) -> Option<&'a Entry<Level3>> {
+        let p3 = p4.next_table(page.p4_index())?;
+        Some(&p3[page.p3_index()])
+    }
+
+    fn entry_mut<'a>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+    ) -> Option<&'a mut Entry<Level3>> {
+        let p3 = p4.next_table_mut(page.p4_index())?;
+        Some(&mut p3[page.p3_index()])
+    }
+
+    fn entry_create<'a, A>(
+        p4: &'a mut PageTable<Level4>,
+        page: &Page<Self>,
+        allocator: &mut A,
+    ) -> &'a mut Entry<Level3>
+    where
+        A: FrameAllocator<Size4K>,
+    {
+        let p3 = p4.next_table_create(page.p4_index(), allocator);
+        &mut p3[page.p3_index()]
+    }
+}
+
+/// Creates, removes and looks up page -> frame mappings in the active page tables, through the
+/// recursively mapped P4 table.
+pub struct Mapper {
+    p4: NonNull<PageTable<Level4>>,
+}
+
+// Safety: The recursive mapping is the same no matter which context the mapper is used from.
+unsafe impl Send for Mapper {}
+
+impl Mapper {
+    /// Create a mapper for the active page tables.
+    ///
+    /// # Safety
+    ///
+    /// * The active P4 table must be recursively mapped (see the [module level
+    ///   documentation](super)).
+    /// * There must not be any other `Mapper` in use at the same time.
+    pub unsafe fn new() -> Self {
+        Self { p4: P4 }
+    }
+
+    pub fn p4(&self) -> &PageTable<Level4> {
+        // Safety: The P4 table is recursively mapped, and we have exclusive access to it.
+        unsafe { self.p4.as_ref() }
+    }
+
+    pub fn p4_mut(&mut self) -> &mut PageTable<Level4> {
+        // Safety: The P4 table is recursively mapped, and we have exclusive access to it.
+        unsafe { self.p4.as_mut() }
+    }
+
+    /// Translate a virtual address to the physical address it's mapped to, or `None` if it isn't
+    /// mapped. Handles addresses inside 2M and 1G huge pages.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let page = Page::<Size4K>::containing_addr(addr);
+
+        let p3 = self.p4().next_table(page.p4_index())?;
+        let p3_entry = &p3[page.p3_index()];
+        if p3_entry
+            .flags()
+            .contains(EntryFlags::PRESENT | EntryFlags::HUGE)
+        {
+            return Some(p3_entry.addr() + addr.as_usize() % Size1G::SIZE);
+        }
+
+        let p2 = p3.next_table(page.p3_index())?;
+        let p2_entry = &p2[page.p2_index()];
+        if p2_entry
+            .flags()
+            .contains(EntryFlags::PRESENT | EntryFlags::HUGE)
+        {
+            return Some(p2_entry.addr() + addr.as_usize() % Size2M::SIZE);
+        }
+
+        let p1 = p2.next_table(page.p2_index())?;
+        p1[page.p1_index()]
+            .frame()
+            .map(|frame| frame.start_address() + addr.page_offset())
+    }
+
+    /// Map `page` to `frame`, allocating any page tables that don't exist yet from `allocator`.
+    /// The entry is always marked as present.
+    ///
+    /// # Panics
+    ///
+    /// * If `page` is already mapped.
+    /// * If `page` lies inside a larger huge page.
+    pub fn map_to<S, A>(
+        &mut self,
+        page: Page<S>,
+        frame: AllocatedFrame<S>,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) where
+        S: PageSize,
+        A: FrameAllocator<Size4K>,
+    {
+        let entry = S::entry_create(self.p4_mut(), &page, allocator);
+        assert!(entry.is_unused(), "{:?} is already mapped", page);
+        entry.set(
+            frame.start_address(),
+            flags | S::FLAGS | EntryFlags::PRESENT,
+        );
+    }
+
+    /// Map `page` to a newly allocated frame.
+    ///
+    /// # Panics
+    ///
+    /// * If `page` is already mapped.
+    /// * If `page` lies inside a larger huge page.
+    /// * If the allocator has run out of frames.
+    pub fn map<S, A>(&mut self, page: Page<S>, flags: EntryFlags, allocator: &mut A)
+    where
+        S: PageSize,
+        A: FrameAllocator<Size4K> + FrameAllocator<S>,
+    {
+        let frame = FrameAllocator::<S>::allocate(allocator).expect("Out of memory");
+        self.map_to(page, frame, flags, allocator);
+    }
+
+    /// Map the page with the same address as `frame` to it.
+    ///
+    /// # Panics
+    ///
+    /// * If the frame's address isn't a valid virtual address.
+    /// * If the page is already mapped.
+    /// * If the page lies inside a larger huge page.
+    pub fn identity_map<S, A>(
+        &mut self,
+        frame: AllocatedFrame<S>,
+        flags: EntryFlags,
+        allocator: &mut A,
+    ) where
+        S: PageSize,
+        A: FrameAllocator<Size4K>,
+    {
+        let page = Page::containing_addr(VirtAddr::new(frame.start_address().as_usize()));
+        self.map_to(page, frame, flags, allocator);
+    }
+
+    /// Remove the mapping for `page`, returning the frame it was mapped to, and flush it from the
+    /// TLB.
+    ///
+    /// # Panics
+    ///
+    /// * If `page` isn't mapped.
+    pub fn unmap<S: PageSize>(&mut self, page: Page<S>) -> AllocatedFrame<S> {
+        let entry = S::entry_mut(self.p4_mut(), &page)
+            .filter(|entry| entry.flags().contains(EntryFlags::PRESENT | S::FLAGS))
+            .unwrap_or_else(|| panic!("{:?} is not mapped", page));
+        // Safety: The frame must've been allocated. This is safe if page -> frame mappings are
+        // created safely, because you can only map allocated frames
+        let frame = unsafe { AllocatedFrame::containing_addr(entry.addr()) };
+        entry.set_unused();
+        tlb::flush(x86_64::VirtAddr::new(page.start_address().into()));
+        frame
+    }
+
+    /// Returns the flags of the entry mapping `page`, or `None` if it isn't mapped.
+    pub fn flags<S: PageSize>(&self, page: &Page<S>) -> Option<EntryFlags> {
+        S::entry(self.p4(), page)
+            .map(|entry| entry.flags())
+            .filter(|flags| flags.contains(EntryFlags::PRESENT | S::FLAGS))
+    }
+}
@@ -5,66 +5,342 @@
 //! * The 511th entry of the active P4 table must always be mapped to the active P4 table itself.
 
 #![allow(dead_code)]
-mod entry;
-pub use entry::{Entry, EntryFlags};
+pub use rux_memory::{
+    entry::{Entry, EntryFlags},
+    level::{HigherPageTableLevel, Level1, Level2, Level3, Level4, PageTableLevel},
+};
+mod mapper;
+pub use mapper::{Mapper, PageSize};
 mod table;
 pub use table::PageTable;
+mod temporary_page;
+pub use temporary_page::TemporaryPage;
+mod walk;
+pub use walk::TableWalk;
 
-use core::marker::PhantomData;
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
 
-use super::{Addr, SizedRegion};
+use multiboot2::ElfSectionType;
+use x86_64::{
+    instructions::tlb,
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::PhysFrame,
+};
 
-/// Specifies a page table level
-pub trait PageTableLevel: Copy {}
+use self::table::RECURSIVE_ENTRY;
+use super::{
+    phys::{AllocatedFrame, FrameAllocator},
+    PhysAddr, Size4K, SizedRegion, VirtAddr,
+};
 
-/// Specifies a page table level where the next level down is another page table
-pub trait HigherPageTableLevel: PageTableLevel {
-    /// Level of the next page table down
-    type NextLevel: PageTableLevel;
+/// A virtual page of memory
+pub struct Page<S: SizedRegion> {
+    start: VirtAddr,
+    _marker: PhantomData<S>,
+}
+
+impl<S: SizedRegion> Clone for Page<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
-#[derive(Clone, Copy)]
-pub enum Level1 {}
-#[derive(Clone, Copy)]
-pub enum Level2 {}
-#[derive(Clone, Copy)]
-pub enum Level3 {}
-#[derive(Clone, Copy)]
-pub enum Level4 {}
+impl<S: SizedRegion> Copy for Page<S> {}
+
+impl<S: SizedRegion> Page<S> {
+    pub fn containing_addr(addr: VirtAddr) -> Self {
+        Self {
+            start: addr.align_down(S::SIZE),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn start_address(&self) -> VirtAddr {
+        self.start
+    }
+
+    fn p4_index(&self) -> usize {
+        self.start.p4_index()
+    }
+
+    fn p3_index(&self) -> usize {
+        self.start.p3_index()
+    }
+
+    fn p2_index(&self) -> usize {
+        self.start.p2_index()
+    }
 
-impl PageTableLevel for Level1 {}
-impl PageTableLevel for Level2 {}
-impl PageTableLevel for Level3 {}
-impl PageTableLevel for Level4 {}
+    fn p1_index(&self) -> usize {
+        self.start.p1_index()
+    }
+}
 
-impl HigherPageTableLevel for Level2 {
-    type NextLevel = Level1;
+impl<S: SizedRegion> fmt::Debug for Page<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Page")
+            .field(&self.start)
+            .field(&S::DISPLAY)
+            .finish()
+    }
 }
 
-impl HigherPageTableLevel for Level3 {
-    type NextLevel = Level2;
+/// The page table hierarchy that is currently loaded into CR3.
+pub struct ActivePageTable {
+    mapper: Mapper,
 }
 
-impl HigherPageTableLevel for Level4 {
-    type NextLevel = Level3;
+impl Deref for ActivePageTable {
+    type Target = Mapper;
+
+    fn deref(&self) -> &Mapper {
+        &self.mapper
+    }
 }
 
-/// A virtual page of memory
-pub struct Page<S: SizedRegion> {
-    start: Addr,
-    _marker: PhantomData<S>,
+impl DerefMut for ActivePageTable {
+    fn deref_mut(&mut self) -> &mut Mapper {
+        &mut self.mapper
+    }
 }
 
-impl<S: SizedRegion> Page<S> {
-    fn containing_addr(addr: Addr) -> Self {
-        assert!(
-            addr.0 < 0x0000_8000_0000_0000 || addr.0 >= 0xffff_8000_0000_0000,
-            "invalid addr: {}",
-            addr
-        );
+impl ActivePageTable {
+    /// # Safety
+    ///
+    /// There must only be one `ActivePageTable` (or [`Mapper`]) at a time.
+    pub unsafe fn new() -> Self {
         Self {
-            start: addr.align_down(S::SIZE),
-            _marker: PhantomData,
+            mapper: Mapper::new(),
+        }
+    }
+
+    /// Run `f` with a [`Mapper`] that edits `table` instead of the active page table, by pointing
+    /// the recursive entry of the active P4 table at `table` for the duration of the call.
+    pub fn with<F>(
+        &mut self,
+        table: &mut InactivePageTable,
+        temporary_page: &mut TemporaryPage,
+        f: F,
+    ) where
+        F: FnOnce(&mut Mapper),
+    {
+        {
+            // Safety: This is the frame of the active P4 table, which we own.
+            let backup = unsafe { AllocatedFrame::containing_addr(Self::p4_addr()) };
+            // Map the active P4 table somewhere else, so the recursive entry can be restored
+            let p4_table = temporary_page.map_table_frame(&backup, self);
+
+            self.p4_mut()[RECURSIVE_ENTRY].set(
+                table.p4_frame.start_address(),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            );
+            tlb::flush_all();
+
+            f(&mut self.mapper);
+
+            p4_table[RECURSIVE_ENTRY].set(
+                backup.start_address(),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            );
+            tlb::flush_all();
         }
+
+        temporary_page.unmap(self);
     }
+
+    /// Load `new_table` into CR3, returning the previously active table.
+    pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
+        let old_table = InactivePageTable {
+            // Safety: This is the frame of the active P4 table, which we own.
+            p4_frame: unsafe { AllocatedFrame::containing_addr(Self::p4_addr()) },
+        };
+        let frame = PhysFrame::containing_address(x86_64::PhysAddr::new(
+            new_table.p4_frame.start_address().into(),
+        ));
+        // Safety: The new table is a valid, recursively mapped page table hierarchy.
+        unsafe { Cr3::write(frame, Cr3Flags::empty()) };
+        old_table
+    }
+
+    /// Returns the physical address of the active P4 table.
+    fn p4_addr() -> PhysAddr {
+        PhysAddr::new(Cr3::read().0.start_address().as_u64() as usize)
+    }
+}
+
+/// A page table hierarchy that isn't currently loaded, which can be edited using
+/// [`ActivePageTable::with`].
+pub struct InactivePageTable {
+    p4_frame: AllocatedFrame<Size4K>,
+}
+
+impl InactivePageTable {
+    /// Create a new, empty page table hierarchy, using `frame` for its P4 table.
+    pub fn new(
+        frame: AllocatedFrame<Size4K>,
+        active_table: &mut ActivePageTable,
+        temporary_page: &mut TemporaryPage,
+    ) -> Self {
+        {
+            let table = temporary_page.map_table_frame(&frame, active_table);
+            table.zero();
+            // Set up the recursive mapping
+            table[RECURSIVE_ENTRY].set(
+                frame.start_address(),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            );
+        }
+        temporary_page.unmap(active_table);
+
+        Self { p4_frame: frame }
+    }
+}
+
+/// The page used by [`remap_the_kernel`] to edit the new page tables.
+const REMAP_TEMPORARY_PAGE: VirtAddr = VirtAddr::new(0xcafebabe000);
+
+/// Build a new page table hierarchy that identity maps each of the kernel's ELF sections with
+/// the permissions it asks for (writable only if `SHF_WRITE`, and executable only if
+/// `SHF_EXECINSTR`), as well as the VGA text buffer and the multiboot information structure,
+/// and switch to it. This replaces the page tables set up in `boot.asm`, which map the first
+/// gibibyte as writable and executable.
+pub fn remap_the_kernel<A>(allocator: &mut A, mb: &multiboot2::BootInformation) -> ActivePageTable
+where
+    A: FrameAllocator<Size4K>,
+{
+    enable_nxe_bit();
+    enable_write_protect_bit();
+
+    let mut temporary_page =
+        TemporaryPage::new(Page::containing_addr(REMAP_TEMPORARY_PAGE), allocator);
+    // Safety: This is called once, during boot, before anything else touches the page tables.
+    let mut active_table = unsafe { ActivePageTable::new() };
+    let mut new_table = {
+        let frame = allocator
+            .allocate()
+            .expect("No frames left for the new P4 table");
+        InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
+    };
+
+    active_table.with(&mut new_table, &mut temporary_page, |mapper| {
+        let elf_tag = mb
+            .elf_sections_tag()
+            .expect("Multiboot2 ELF sections tag required");
+
+        for section in elf_tag.sections() {
+            if section.size() == 0 || section.start_address() == 0 {
+                continue;
+            }
+            // Of the sections that aren't allocated, only the symbol and string tables are used,
+            // to symbolise backtraces
+            let symbols = section.section_type() == ElfSectionType::LinkerSymbolTable
+                || (section.section_type() == ElfSectionType::StringTable
+                    && section.name() == ".strtab");
+            if !section.is_allocated() && !symbols {
+                continue;
+            }
+            if section.is_allocated() {
+                assert!(
+                    (section.start_address() as usize).is_multiple_of(Size4K::SIZE),
+                    "Section {} must be page aligned",
+                    section.name()
+                );
+            }
+
+            let flags = elf_section_flags(&section);
+            let start = PhysAddr::new(section.start_address() as usize);
+            let end = PhysAddr::new(section.end_address() as usize);
+            identity_map_range(mapper, start..end, flags, allocator);
+        }
+
+        // The VGA text buffer
+        identity_map_range(
+            mapper,
+            PhysAddr::new(0xb8000)..PhysAddr::new(0xb8000 + Size4K::SIZE),
+            EntryFlags::WRITABLE | EntryFlags::NO_EXEC,
+            allocator,
+        );
+
+        // The multiboot information structure
+        identity_map_range(
+            mapper,
+            PhysAddr::new(mb.start_address())..PhysAddr::new(mb.end_address()),
+            EntryFlags::NO_EXEC,
+            allocator,
+        );
+    });
+
+    active_table.switch(new_table);
+    active_table
+}
+
+/// Returns the flags to map an ELF section with: writable only if the section is writable, and
+/// executable only if it contains instructions.
+fn elf_section_flags(section: &multiboot2::ElfSection) -> EntryFlags {
+    use multiboot2::ElfSectionFlags;
+
+    let mut flags = EntryFlags::empty();
+    if section.flags().contains(ElfSectionFlags::ALLOCATED) {
+        flags |= EntryFlags::PRESENT;
+    }
+    if section.flags().contains(ElfSectionFlags::WRITABLE) {
+        flags |= EntryFlags::WRITABLE;
+    }
+    if !section.flags().contains(ElfSectionFlags::EXECUTABLE) {
+        flags |= EntryFlags::NO_EXEC;
+    }
+    flags
+}
+
+/// Identity map every frame overlapping `range` that isn't already mapped. Non-allocated ELF
+/// sections aren't page aligned, so they may share a frame with something mapped earlier.
+///
+/// # Panics
+///
+/// * If `range` extends past the lower half of the virtual address space, so can't be identity
+///   mapped.
+pub(super) fn identity_map_range<A>(
+    mapper: &mut Mapper,
+    range: core::ops::Range<PhysAddr>,
+    flags: EntryFlags,
+    allocator: &mut A,
+) where
+    A: FrameAllocator<Size4K>,
+{
+    let mut addr = range.start.align_down(Size4K::SIZE);
+    while addr < range.end {
+        if mapper.translate(VirtAddr::new(addr.as_usize())).is_none() {
+            // Safety: These frames are reserved, as they hold the kernel, the multiboot
+            // information, or memory-mapped hardware, so the frame allocator never hands them out.
+            let frame = unsafe { AllocatedFrame::<Size4K>::containing_addr(addr) };
+            mapper.identity_map(frame, flags, allocator);
+        }
+        addr += Size4K::SIZE;
+    }
+}
+
+/// Returns whether `addr` is mapped in the active page tables, so can be accessed without a page
+/// fault (though it may not be writable).
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    TableWalk::new(addr).is_mapped()
+}
+
+/// Enable the `NO_EXEC` bit in page table entries.
+fn enable_nxe_bit() {
+    use x86_64::registers::model_specific::{Efer, EferFlags};
+
+    // Safety: Setting NXE only makes the `NO_EXEC` bit meaningful, which we don't use yet.
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+}
+
+/// Make the kernel respect the `WRITABLE` bit of page table entries.
+fn enable_write_protect_bit() {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    // Safety: Everything the kernel writes to is mapped as writable.
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
 }
@@ -5,11 +5,12 @@ use core::{
 };
 
 use super::{Entry, EntryFlags, HigherPageTableLevel, Level4, PageTableLevel};
+use crate::memory::{phys::FrameAllocator, Size4K};
 
 /// The number of entries in a page table
 const ENTRY_COUNT: usize = 512;
 /// The entry of the p4 table that maps to the p4 table itself.
-const RECURSIVE_ENTRY: usize = 511;
+pub const RECURSIVE_ENTRY: usize = 511;
 
 /// Pointer to the P4 table, assuming it is mapped recursively
 pub const P4: NonNull<PageTable<Level4>> =
@@ -39,6 +40,47 @@ impl<L: HigherPageTableLevel> PageTable<L> {
         self.next_table_ptr(index)
             .map(|mut p| unsafe { p.as_mut() })
     }
+
+    /// Get the next page table down, allocating and zeroing a new one if it doesn't exist yet.
+    ///
+    /// # Panics
+    ///
+    /// * If the entry maps a huge page, rather than pointing to another page table.
+    /// * If the allocator has run out of frames.
+    pub fn next_table_create<A>(
+        &mut self,
+        index: usize,
+        allocator: &mut A,
+    ) -> &mut PageTable<L::NextLevel>
+    where
+        A: FrameAllocator<Size4K>,
+    {
+        if self.next_table(index).is_none() {
+            assert!(
+                !self.0[index].flags().contains(EntryFlags::HUGE),
+                "Entry {} maps a huge page, not a page table",
+                index
+            );
+            let frame = allocator
+                .allocate()
+                .expect("No frames left for a page table");
+            self.0[index].set(
+                frame.start_address(),
+                EntryFlags::PRESENT | EntryFlags::WRITABLE,
+            );
+            self.next_table_mut(index).unwrap().zero();
+        }
+        self.next_table_mut(index).unwrap()
+    }
+}
+
+impl<L: PageTableLevel> PageTable<L> {
+    /// Mark every entry in the table as unused.
+    pub fn zero(&mut self) {
+        for entry in self.0.iter_mut() {
+            entry.set_unused();
+        }
+    }
 }
 
 impl<L: PageTableLevel> Default for PageTable<L> {
@@ -49,7 +91,7 @@ impl<L: PageTableLevel> Default for PageTable<L> {
 
 impl<Idx, L> Index<Idx> for PageTable<L>
 where
-    Idx: SliceIndex<[Entry<L>; ENTRY_COUNT]>,
+    Idx: SliceIndex<[Entry<L>]>,
     L: PageTableLevel,
 {
     type Output = Idx::Output;
@@ -61,7 +103,7 @@ where
 
 impl<Idx, L> IndexMut<Idx> for PageTable<L>
 where
-    Idx: SliceIndex<[Entry<L>; ENTRY_COUNT]>,
+    Idx: SliceIndex<[Entry<L>]>,
     L: PageTableLevel,
 {
     fn index_mut(&mut self, idx: Idx) -> &mut Idx::Output {
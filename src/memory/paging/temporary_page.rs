@@ -0,0 +1,88 @@
+use super::{ActivePageTable, EntryFlags, Level4, Page, PageTable};
+use crate::memory::{
+    phys::{AllocatedFrame, FrameAllocator},
+    Size4K, VirtAddr,
+};
+
+/// A page that can be temporarily mapped to an arbitrary frame, E.G. to edit an inactive page
+/// table.
+pub struct TemporaryPage {
+    page: Page<Size4K>,
+    allocator: TinyAllocator,
+}
+
+impl TemporaryPage {
+    /// Create a temporary page, taking the frames needed for the page tables to map it from
+    /// `allocator`.
+    pub fn new<A: FrameAllocator<Size4K>>(page: Page<Size4K>, allocator: &mut A) -> Self {
+        Self {
+            page,
+            allocator: TinyAllocator::new(allocator),
+        }
+    }
+
+    /// Map the temporary page to `frame` in the active page table, returning its start address.
+    /// The frame is still owned by the caller.
+    ///
+    /// # Panics
+    ///
+    /// * If the temporary page is already mapped.
+    pub fn map(
+        &mut self,
+        frame: &AllocatedFrame<Size4K>,
+        active_table: &mut ActivePageTable,
+    ) -> VirtAddr {
+        // Safety: This is only a second handle to a frame the caller already owns, and it is
+        // discarded again by `unmap`.
+        let frame = unsafe { AllocatedFrame::containing_addr(frame.start_address()) };
+        active_table.map_to(
+            self.page,
+            frame,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXEC,
+            &mut self.allocator,
+        );
+        self.page.start_address()
+    }
+
+    /// Map the temporary page to a frame containing a page table, and return a reference to that
+    /// table.
+    pub fn map_table_frame(
+        &mut self,
+        frame: &AllocatedFrame<Size4K>,
+        active_table: &mut ActivePageTable,
+    ) -> &mut PageTable<Level4> {
+        // Safety: The frame is now mapped at the temporary page, and contains a page table.
+        unsafe { &mut *self.map(frame, active_table).as_mut_ptr() }
+    }
+
+    /// Unmap the temporary page from the active page table.
+    pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
+        // The frame is owned by whoever called `map`, so the handle returned here is discarded
+        active_table.unmap(self.page);
+    }
+}
+
+/// A frame allocator holding just enough frames to map a single page (one P3, P2, and P1 table).
+struct TinyAllocator([Option<AllocatedFrame<Size4K>>; 3]);
+
+impl TinyAllocator {
+    fn new<A: FrameAllocator<Size4K>>(allocator: &mut A) -> Self {
+        let mut frame = || allocator.allocate();
+        Self([frame(), frame(), frame()])
+    }
+}
+
+impl FrameAllocator<Size4K> for TinyAllocator {
+    fn allocate(&mut self) -> Option<AllocatedFrame<Size4K>> {
+        self.0.iter_mut().find_map(Option::take)
+    }
+
+    fn deallocate(&mut self, frame: AllocatedFrame<Size4K>) {
+        let slot = self
+            .0
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("Tiny allocator can only hold 3 frames");
+        *slot = Some(frame);
+    }
+}
@@ -0,0 +1,80 @@
+use core::fmt;
+
+use super::{table::P4, EntryFlags, Page};
+use crate::memory::{PhysAddr, Size4K, VirtAddr};
+
+/// Names of the page table levels, from the top down.
+const LEVEL_NAMES: [&str; 4] = ["P4", "P3", "P2", "P1"];
+
+/// A snapshot of the entries the MMU walks through to translate a virtual address, from the P4
+/// entry downwards. Useful for debugging, E.G. after a page fault.
+#[derive(Clone, Copy)]
+pub struct TableWalk {
+    addr: VirtAddr,
+    /// Index, address and flags of the entry at each level. `None` for levels that aren't
+    /// reached, because a higher entry isn't present or maps a huge page.
+    levels: [Option<(usize, PhysAddr, EntryFlags)>; 4],
+}
+
+impl TableWalk {
+    /// Walk the active page tables for `addr`, through the recursive mapping. This only reads the
+    /// tables, so it is safe to use while another [`Mapper`](super::Mapper) exists, E.G. from an
+    /// exception handler.
+    pub fn new(addr: VirtAddr) -> Self {
+        let page = Page::<Size4K>::containing_addr(addr);
+        let mut levels = [None; 4];
+
+        // Safety: The active P4 table is always recursively mapped.
+        let p4 = unsafe { P4.as_ref() };
+        let entry = &p4[page.p4_index()];
+        levels[0] = Some((page.p4_index(), entry.addr(), entry.flags()));
+
+        if let Some(p3) = p4.next_table(page.p4_index()) {
+            let entry = &p3[page.p3_index()];
+            levels[1] = Some((page.p3_index(), entry.addr(), entry.flags()));
+
+            if let Some(p2) = p3.next_table(page.p3_index()) {
+                let entry = &p2[page.p2_index()];
+                levels[2] = Some((page.p2_index(), entry.addr(), entry.flags()));
+
+                if let Some(p1) = p2.next_table(page.p2_index()) {
+                    let entry = &p1[page.p1_index()];
+                    levels[3] = Some((page.p1_index(), entry.addr(), entry.flags()));
+                }
+            }
+        }
+
+        Self { addr, levels }
+    }
+
+    /// Returns whether the address is mapped, I.E. whether reading it wouldn't page fault.
+    pub fn is_mapped(&self) -> bool {
+        self.levels
+            .iter()
+            .enumerate()
+            .any(|(level, entry)| match entry {
+                Some((_, _, flags)) => {
+                    flags.contains(EntryFlags::PRESENT)
+                        && (level == LEVEL_NAMES.len() - 1 || flags.contains(EntryFlags::HUGE))
+                }
+                None => false,
+            })
+    }
+}
+
+impl fmt::Display for TableWalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Page table walk for {}:", self.addr)?;
+        for (name, level) in LEVEL_NAMES.iter().zip(self.levels.iter()) {
+            if let Some((index, addr, flags)) = level {
+                write!(f, "\n  {}[{:3}]: {} {:?}", name, index, addr, flags)?;
+                if !flags.contains(EntryFlags::PRESENT) {
+                    write!(f, " (not present)")?;
+                } else if flags.contains(EntryFlags::HUGE) {
+                    write!(f, " (huge page)")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{kernel_range, multiboot_range, AllocatedFrame, FrameAllocator, MAX_ADDR};
+use crate::memory::{PhysAddr, Size4K, SizedRegion};
+
+/// Number of frames covered by the bitmap.
+const FRAME_COUNT: usize = MAX_ADDR / Size4K::SIZE;
+/// Number of frames covered by a single word of the bitmap.
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Storage space for the bitmap. A set bit means the corresponding frame is free, so the
+/// zero-initialised bitmap (which lives in `.bss`) starts out with every frame in use.
+static mut BITMAP: [u64; FRAME_COUNT / WORD_BITS] = [0; FRAME_COUNT / WORD_BITS];
+/// Whether a [`BitmapFrameAllocator`] has already claimed [`BITMAP`].
+static BITMAP_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// A frame allocator that keeps one bit per physical frame, allowing frames to be allocated and
+/// freed in any order.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// Index of the first word of the bitmap that may contain a free frame.
+    next_word: usize,
+    /// Number of frames currently available for allocation.
+    free_frames: usize,
+}
+
+#[allow(dead_code)]
+impl BitmapFrameAllocator {
+    /// Create the allocator from the available areas of the multiboot memory map, excluding the
+    /// kernel and the multiboot information structure.
+    ///
+    /// # Panics
+    ///
+    /// * If a `BitmapFrameAllocator` has already been created.
+    pub fn new(mb: &multiboot2::BootInformation) -> Self {
+        assert!(
+            !BITMAP_TAKEN.swap(true, Ordering::AcqRel),
+            "The frame bitmap is already in use"
+        );
+        // Safety: The check above ensures this is the only reference to the bitmap that will ever
+        // be created.
+        let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(BITMAP) };
+
+        let mut allocator = Self {
+            bitmap,
+            next_word: 0,
+            free_frames: 0,
+        };
+
+        let memory_map = mb
+            .memory_map_tag()
+            .expect("Multiboot2 memory map tag required");
+        for area in memory_map.memory_areas() {
+            // Only whole frames inside the area are usable
+            let start = PhysAddr::new(area.start_address() as usize).align_up(Size4K::SIZE);
+            let end = PhysAddr::new(area.end_address() as usize).align_down(Size4K::SIZE);
+            allocator.mark_range(start..end, true);
+        }
+
+        // Partially used frames at either end of a reserved range are in use too
+        for range in [kernel_range(mb), multiboot_range(mb)] {
+            let start = range.start.align_down(Size4K::SIZE);
+            let end = range.end.align_up(Size4K::SIZE);
+            allocator.mark_range(start..end, false);
+        }
+
+        allocator
+    }
+
+    /// Returns the number of frames currently available for allocation.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Mark every frame in the (frame aligned) range as either free or in use.
+    fn mark_range(&mut self, range: Range<PhysAddr>, free: bool) {
+        let start = range.start.as_usize() / Size4K::SIZE;
+        let end = (range.end.as_usize() / Size4K::SIZE).min(FRAME_COUNT);
+        for index in start..end {
+            self.mark(index, free);
+        }
+    }
+
+    /// Mark a single frame as either free or in use.
+    fn mark(&mut self, index: usize, free: bool) {
+        let (word, bit) = (index / WORD_BITS, 1 << (index % WORD_BITS));
+        let was_free = self.bitmap[word] & bit != 0;
+        if free && !was_free {
+            self.bitmap[word] |= bit;
+            self.free_frames += 1;
+            self.next_word = self.next_word.min(word);
+        } else if !free && was_free {
+            self.bitmap[word] &= !bit;
+            self.free_frames -= 1;
+        }
+    }
+}
+
+impl FrameAllocator<Size4K> for BitmapFrameAllocator {
+    fn allocate(&mut self) -> Option<AllocatedFrame<Size4K>> {
+        let word = (self.next_word..self.bitmap.len()).find(|&w| self.bitmap[w] != 0)?;
+        self.next_word = word;
+
+        let index = word * WORD_BITS + self.bitmap[word].trailing_zeros() as usize;
+        self.mark(index, false);
+        // Safety: The frame was free, and is now marked as in use.
+        Some(unsafe { AllocatedFrame::containing_addr(PhysAddr::new(index * Size4K::SIZE)) })
+    }
+
+    fn deallocate(&mut self, frame: AllocatedFrame<Size4K>) {
+        let index = frame.start_address().as_usize() / Size4K::SIZE;
+        assert!(
+            index < FRAME_COUNT && self.bitmap[index / WORD_BITS] & (1 << (index % WORD_BITS)) == 0,
+            "Deallocating {:?}, which is not allocated",
+            frame
+        );
+        self.mark(index, true);
+    }
+}
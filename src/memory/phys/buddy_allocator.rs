@@ -0,0 +1,217 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{kernel_range, multiboot_range, AllocatedFrame, FrameAllocator, MAX_ADDR};
+use crate::memory::{PhysAddr, Size1G, Size4K, SizedRegion};
+
+/// Number of 4K frames covered by the allocator.
+const FRAME_COUNT: usize = MAX_ADDR / Size4K::SIZE;
+/// The largest order of block handed out (a block of order `n` is `2^n` frames long).
+const MAX_ORDER: usize = order_of(Size1G::SIZE);
+/// Number of blocks covered by a single word of a bitmap.
+const WORD_BITS: usize = u64::BITS as usize;
+/// Total number of words needed to store the bitmaps for every order.
+const BITMAP_WORDS: usize = order_offset(MAX_ORDER + 1);
+
+/// Returns the order of a block that is `size` bytes long.
+const fn order_of(size: usize) -> usize {
+    (size / Size4K::SIZE).trailing_zeros() as usize
+}
+
+/// Returns the number of words in the bitmap for blocks of `order`.
+const fn order_words(order: usize) -> usize {
+    (FRAME_COUNT >> order).div_ceil(WORD_BITS)
+}
+
+/// Returns the index of the first word of the bitmap for blocks of `order`.
+const fn order_offset(order: usize) -> usize {
+    let mut offset = 0;
+    let mut o = 0;
+    while o < order {
+        offset += order_words(o);
+        o += 1;
+    }
+    offset
+}
+
+/// Storage space for the per-order bitmaps, one after another. A set bit means the corresponding
+/// block is free, and is not part of a larger free block.
+static mut BITMAP: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+/// Whether a [`BuddyFrameAllocator`] has already claimed [`BITMAP`].
+static BITMAP_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// A buddy-system frame allocator, which can hand out (and take back) 4K, 2M and 1G frames.
+/// Larger blocks are split in half to satisfy smaller allocations, and freed blocks are merged
+/// with their buddy whenever it is free as well.
+pub struct BuddyFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// Number of free blocks of each order.
+    free_blocks: [usize; MAX_ORDER + 1],
+    /// Index (relative to the start of that order's bitmap) of the first word that may contain a
+    /// free block of each order.
+    next_word: [usize; MAX_ORDER + 1],
+}
+
+#[allow(dead_code)]
+impl BuddyFrameAllocator {
+    /// Create the allocator from the available areas of the multiboot memory map, excluding the
+    /// kernel and the multiboot information structure.
+    ///
+    /// # Panics
+    ///
+    /// * If a `BuddyFrameAllocator` has already been created.
+    pub fn new(mb: &multiboot2::BootInformation) -> Self {
+        assert!(
+            !BITMAP_TAKEN.swap(true, Ordering::AcqRel),
+            "The buddy allocator bitmap is already in use"
+        );
+        // Safety: The check above ensures this is the only reference to the bitmap that will ever
+        // be created.
+        let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(BITMAP) };
+
+        let mut allocator = Self {
+            bitmap,
+            free_blocks: [0; MAX_ORDER + 1],
+            next_word: [0; MAX_ORDER + 1],
+        };
+
+        // Partially used frames at either end of a reserved range are in use too
+        let mut reserved = [kernel_range(mb), multiboot_range(mb)]
+            .map(|r| r.start.align_down(Size4K::SIZE)..r.end.align_up(Size4K::SIZE));
+        reserved.sort_unstable_by_key(|r| r.start);
+
+        let memory_map = mb
+            .memory_map_tag()
+            .expect("Multiboot2 memory map tag required");
+        for area in memory_map.memory_areas() {
+            // Only whole frames inside the area are usable
+            let mut start = PhysAddr::new(area.start_address() as usize).align_up(Size4K::SIZE);
+            let end = PhysAddr::new(area.end_address() as usize).align_down(Size4K::SIZE);
+            for range in &reserved {
+                if range.end > start && range.start < end {
+                    allocator.free_range(start..range.start.max(start));
+                    start = range.end;
+                }
+            }
+            allocator.free_range(start..end);
+        }
+
+        allocator
+    }
+
+    /// Returns the number of 4K frames currently available for allocation.
+    pub fn free_frames(&self) -> usize {
+        self.free_blocks
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+
+    /// Add every frame in the (frame aligned) range to the allocator, using the largest blocks
+    /// possible.
+    fn free_range(&mut self, range: Range<PhysAddr>) {
+        let mut frame = range.start.as_usize() / Size4K::SIZE;
+        let end = (range.end.as_usize() / Size4K::SIZE).min(FRAME_COUNT);
+        while frame < end {
+            let mut order = (frame.trailing_zeros() as usize).min(MAX_ORDER);
+            while frame + (1 << order) > end {
+                order -= 1;
+            }
+            self.free_block(order, frame >> order);
+            frame += 1 << order;
+        }
+    }
+
+    /// Returns whether block `index` of `order` is free.
+    fn is_free(&self, order: usize, index: usize) -> bool {
+        let word = order_offset(order) + index / WORD_BITS;
+        self.bitmap[word] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Mark block `index` of `order` as either free or in use.
+    fn set_free(&mut self, order: usize, index: usize, free: bool) {
+        let word = index / WORD_BITS;
+        let bit = 1 << (index % WORD_BITS);
+        if free {
+            self.bitmap[order_offset(order) + word] |= bit;
+            self.free_blocks[order] += 1;
+            self.next_word[order] = self.next_word[order].min(word);
+        } else {
+            self.bitmap[order_offset(order) + word] &= !bit;
+            self.free_blocks[order] -= 1;
+        }
+    }
+
+    /// Allocate a block of `order`, splitting larger blocks if needed, and return its index.
+    fn allocate_block(&mut self, order: usize) -> Option<usize> {
+        if self.free_blocks[order] > 0 {
+            let offset = order_offset(order);
+            let word = (self.next_word[order]..order_words(order))
+                .find(|&w| self.bitmap[offset + w] != 0)
+                .expect("Buddy allocator free block count is out of sync with its bitmap");
+            self.next_word[order] = word;
+
+            let index = word * WORD_BITS + self.bitmap[offset + word].trailing_zeros() as usize;
+            self.set_free(order, index, false);
+            Some(index)
+        } else if order < MAX_ORDER {
+            // Split a larger block in half, keeping the first half and freeing its buddy
+            let index = self.allocate_block(order + 1)? * 2;
+            self.set_free(order, index + 1, true);
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the order of the free block containing block `index` of `order`, if there is one.
+    /// Free blocks are merged with their buddies, so this may be a larger block than the one
+    /// asked about.
+    fn free_containing(&self, order: usize, index: usize) -> Option<usize> {
+        (order..=MAX_ORDER).find(|&o| self.is_free(o, index >> (o - order)))
+    }
+
+    /// Free block `index` of `order`, merging it with its buddy if that is free too.
+    ///
+    /// # Panics
+    ///
+    /// * If the block, or a larger block containing it, is already free.
+    fn free_block(&mut self, order: usize, index: usize) {
+        if let Some(free_order) = self.free_containing(order, index) {
+            panic!(
+                "Freeing a block of order {} at index {} twice (it's part of a free block of \
+                 order {})",
+                order, index, free_order
+            );
+        }
+        let buddy = index ^ 1;
+        if order < MAX_ORDER && self.is_free(order, buddy) {
+            self.set_free(order, buddy, false);
+            self.free_block(order + 1, index / 2);
+        } else {
+            self.set_free(order, index, true);
+        }
+    }
+}
+
+impl<S: SizedRegion> FrameAllocator<S> for BuddyFrameAllocator {
+    fn allocate(&mut self) -> Option<AllocatedFrame<S>> {
+        let order = order_of(S::SIZE);
+        let index = self.allocate_block(order)?;
+        // Safety: The block was free, and is now marked as in use.
+        Some(unsafe { AllocatedFrame::containing_addr(PhysAddr::new(index * S::SIZE)) })
+    }
+
+    fn deallocate(&mut self, frame: AllocatedFrame<S>) {
+        let index = frame.start_address().as_usize() / S::SIZE;
+        assert!(
+            frame.start_address().as_usize() < MAX_ADDR,
+            "Deallocating {:?}, which is not managed by the allocator",
+            frame
+        );
+        self.free_block(order_of(S::SIZE), index);
+    }
+}
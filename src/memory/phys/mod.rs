@@ -1,62 +1,41 @@
+#[cfg(feature = "frame_alloc_bitmap")]
+mod bitmap_allocator;
+#[cfg(feature = "frame_alloc_bitmap")]
+pub use bitmap_allocator::BitmapFrameAllocator;
+#[cfg(feature = "frame_alloc_buddy")]
+mod buddy_allocator;
+#[cfg(feature = "frame_alloc_buddy")]
+pub use buddy_allocator::BuddyFrameAllocator;
 #[cfg(feature = "frame_alloc_simple")]
 mod simple_allocator;
 #[cfg(feature = "frame_alloc_simple")]
 pub use simple_allocator::SimpleFrameAllocator;
 
-use core::{fmt, marker::PhantomData};
+use core::ops::Range;
 
-use crate::memory::{Addr, Size4K, SizedRegion};
+pub use rux_memory::frame::{AllocatedFrame, FrameAllocator};
 
-/// A physical page frame that is guaranteed to be allocated.
-pub struct AllocatedFrame<S: SizedRegion> {
-    start: Addr,
-    _marker: PhantomData<S>,
-}
+use crate::memory::PhysAddr;
 
-impl<S: SizedRegion> AllocatedFrame<S> {
-    pub unsafe fn containing_addr(addr: Addr) -> Self {
-        Self {
-            start: addr.align_down(S::SIZE),
-            _marker: PhantomData,
-        }
-    }
-}
+/// The highest physical address (exclusive) that the bitmap-based allocators keep track of.
+/// Memory above this is never handed out.
+#[allow(dead_code)]
+const MAX_ADDR: usize = 4 * 1024 * 1024 * 1024;
 
-impl<S: SizedRegion> fmt::Debug for AllocatedFrame<S> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("Frame")
-            .field(&self.start)
-            .field(&S::DISPLAY)
-            .finish()
-    }
-}
-
-pub trait FrameAllocator<S: SizedRegion>: Iterator<Item = AllocatedFrame<S>> {
-    fn deallocate(&mut self, frame: AllocatedFrame<S>);
-}
-
-/// An [`AllocatedFrame`] that is either a normal frame, or a "huge" frame of a certain size
-pub enum SizedAllocatedFrame<S: SizedRegion> {
-    Normal(AllocatedFrame<Size4K>),
-    Huge(AllocatedFrame<S>),
-}
-
-impl<S: SizedRegion> SizedAllocatedFrame<S> {
-    pub unsafe fn new(addr: Addr, huge: bool) -> Self {
-        if huge {
-            Self::Huge(AllocatedFrame::containing_addr(addr))
-        } else {
-            Self::Normal(AllocatedFrame::containing_addr(addr))
-        }
-    }
-}
+/// Returns the range of physical memory occupied by the kernel's ELF sections.
+fn kernel_range(mb: &multiboot2::BootInformation) -> Range<PhysAddr> {
+    let sections = || {
+        mb.elf_sections_tag()
+            .expect("Multiboot2 ELF sections tag required")
+            .sections()
+    };
 
-trait MemoryAreaExt {
-    fn contains(&self, addr: Addr) -> bool;
+    let kernel_start = PhysAddr::new(sections().map(|a| a.start_address()).min().unwrap() as usize);
+    let kernel_end = PhysAddr::new(sections().map(|a| a.end_address()).max().unwrap() as usize);
+    kernel_start..kernel_end
 }
 
-impl MemoryAreaExt for multiboot2::MemoryArea {
-    fn contains(&self, addr: Addr) -> bool {
-        Addr::from(self.end_address()) > addr && addr >= Addr::from(self.start_address())
-    }
+/// Returns the range of physical memory occupied by the multiboot information structure.
+fn multiboot_range(mb: &multiboot2::BootInformation) -> Range<PhysAddr> {
+    PhysAddr::new(mb.start_address())..PhysAddr::new(mb.end_address())
 }
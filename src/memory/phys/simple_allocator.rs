@@ -1,54 +1,44 @@
 use core::ops::Range;
 
-use multiboot2::{MemoryArea, MemoryMapTag};
+use multiboot2::MemoryMapTag;
+use rux_memory::simple_allocator::{self, MemoryMap};
 
-use super::{AllocatedFrame, FrameAllocator, MemoryAreaExt};
-use crate::memory::{Addr, Size4K, SizedRegion};
+use super::{kernel_range, multiboot_range, AllocatedFrame, FrameAllocator};
+use crate::memory::{PhysAddr, Size4K};
 
-pub struct SimpleFrameAllocator<'a> {
-    next: Addr,
-    memory_map: &'a MemoryMapTag,
-    current_area: Option<&'a MemoryArea>,
-    kernel: Range<Addr>,
-    multiboot_info: Range<Addr>,
+/// The memory map from the multiboot information.
+#[derive(Clone, Copy)]
+pub struct MultibootMemoryMap<'a>(&'a MemoryMapTag);
+
+impl MemoryMap for MultibootMemoryMap<'_> {
+    fn available_areas(&self) -> impl Iterator<Item = Range<PhysAddr>> + '_ {
+        self.0.memory_areas().map(|area| {
+            PhysAddr::new(area.start_address() as usize)..PhysAddr::new(area.end_address() as usize)
+        })
+    }
 }
 
+/// A [`simple_allocator::SimpleFrameAllocator`] for the memory described by the multiboot
+/// information.
+#[derive(Clone)]
+pub struct SimpleFrameAllocator<'a>(simple_allocator::SimpleFrameAllocator<MultibootMemoryMap<'a>>);
+
 impl<'a> SimpleFrameAllocator<'a> {
     pub fn new(mb: &'a multiboot2::BootInformation) -> Self {
-        let sections = || {
-            mb.elf_sections_tag()
-                .expect("Multiboot2 ELF sections tag required")
-                .sections()
-        };
-
-        let kernel_start = Addr::from(sections().map(|a| a.start_address()).min().unwrap());
-        let kernel_end = Addr::from(sections().map(|a| a.end_address()).max().unwrap());
-
-        let multiboot_start = Addr::from(mb.start_address());
-        let multiboot_end = Addr::from(mb.end_address());
-
-        let mut allocator = Self {
-            next: Addr::from(0_usize),
-            memory_map: mb
-                .memory_map_tag()
-                .expect("Multiboot2 memory map tag required"),
-            current_area: None,
-            kernel: (kernel_start..kernel_end).into(),
-            multiboot_info: multiboot_start..multiboot_end,
-        };
-        allocator.next_area();
-        allocator
+        let memory_map = mb
+            .memory_map_tag()
+            .expect("Multiboot2 memory map tag required");
+        Self(simple_allocator::SimpleFrameAllocator::new(
+            MultibootMemoryMap(memory_map),
+            kernel_range(mb),
+            multiboot_range(mb),
+        ))
     }
 
-    fn next_area(&mut self) {
-        self.current_area = self
-            .memory_map
-            .memory_areas()
-            .filter(|area| {
-                let address = Addr::from(area.end_address() - 1);
-                address >= self.next
-            })
-            .min_by_key(|area| area.start_address());
+    /// Returns the number of frames still available for allocation. This walks the rest of the
+    /// memory map, so it's slow.
+    pub fn free_frames(&self) -> usize {
+        self.0.free_frames()
     }
 }
 
@@ -56,26 +46,58 @@ impl Iterator for SimpleFrameAllocator<'_> {
     type Item = AllocatedFrame<Size4K>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let area = self.current_area?;
-        let next = if self.kernel.contains(&self.next) {
-            self.kernel.end.align_up(Size4K::SIZE)
-        } else if self.multiboot_info.contains(&self.next) {
-            self.multiboot_info.end.align_up(Size4K::SIZE)
-        } else {
-            self.next
-        };
-        self.next = next + Size4K::SIZE;
-        if !area.contains(next) {
-            self.next_area();
-            self.next()
-        } else {
-            Some(unsafe { AllocatedFrame::containing_addr(next) })
-        }
+        self.0.next()
     }
 }
 
 impl FrameAllocator<Size4K> for SimpleFrameAllocator<'_> {
-    fn deallocate(&mut self, _frame: AllocatedFrame<Size4K>) {
-        unimplemented!()
+    const CAN_DEALLOCATE: bool = false;
+
+    fn allocate(&mut self) -> Option<AllocatedFrame<Size4K>> {
+        self.0.allocate()
+    }
+
+    fn deallocate(&mut self, frame: AllocatedFrame<Size4K>) {
+        self.0.deallocate(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init::boot_info, memory::SizedRegion};
+
+    #[test_case]
+    fn skips_reserved_memory() {
+        let mb = boot_info();
+        let kernel = kernel_range(mb);
+        let multiboot_info = multiboot_range(mb);
+        let memory_map = mb.memory_map_tag().unwrap();
+
+        let mut previous = None;
+        for frame in SimpleFrameAllocator::new(mb) {
+            let start = frame.start_address();
+            let end = start + Size4K::SIZE;
+            assert!(
+                end <= kernel.start || start >= kernel.end,
+                "{:?} overlaps the kernel",
+                frame
+            );
+            assert!(
+                end <= multiboot_info.start || start >= multiboot_info.end,
+                "{:?} overlaps the multiboot information",
+                frame
+            );
+            assert!(
+                memory_map.memory_areas().any(|area| {
+                    PhysAddr::new(area.start_address() as usize) <= start
+                        && end <= PhysAddr::new(area.end_address() as usize)
+                }),
+                "{:?} isn't in available memory",
+                frame
+            );
+            assert!(previous < Some(start), "{:?} was allocated twice", frame);
+            previous = Some(start);
+        }
     }
 }
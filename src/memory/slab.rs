@@ -0,0 +1,656 @@
+//! A slab allocator for small, fixed-size kernel objects.
+//!
+//! Each [`SlabCache`] hands out objects of a single size, carved out of 4K slabs. A slab is a
+//! frame mapped into the slab region, with a [`SlabHeader`] at the start followed by the objects
+//! themselves. Free objects form a linked list threaded through the objects, so allocating and
+//! freeing are constant time. There are general purpose size-class caches from 8 bytes to 2
+//! kibibytes (see [`allocate`]), and subsystems can declare their own named caches for a
+//! particular type with [`ObjectCache`]. The kernel heap serves allocations that fit in a size
+//! class from these caches.
+//!
+//! Caches are only locked with interrupts disabled, so they can be used from interrupt handlers.
+//! Mapping a new slab needs the memory controller, which is always locked after a cache. If it's
+//! already locked, E.G. by code that allocates while holding [`controller`](super::controller),
+//! no slab is mapped and the allocation fails instead of deadlocking (the heap then uses its own
+//! memory).
+
+use core::{
+    alloc::Layout,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{
+    paging::{EntryFlags, Page},
+    phys::FrameAllocator,
+    KernelFrameAllocator, MemoryController, Size4K, SizedRegion, VirtAddr,
+};
+use crate::println;
+
+/// Start of the virtual address range slabs are mapped into (the first address mapped by the
+/// third P4 entry).
+const SLAB_START: usize = 0x0000_0100_0000_0000;
+/// Size of the virtual address range slabs are mapped into.
+const SLAB_REGION_SIZE: usize = 0x0000_0080_0000_0000;
+
+/// Address the next slab will be mapped at. Slab addresses are never reused.
+static NEXT_SLAB: AtomicUsize = AtomicUsize::new(SLAB_START);
+
+/// Every cache that has been used at least once, for [`print_stats`], as a list linked through
+/// [`SlabCache::next_cache`] starting with the last one added. It isn't a `Vec`, because the heap
+/// allocates from the caches, so growing a `Vec` here could recurse.
+static CACHES: AtomicPtr<SlabCache> = AtomicPtr::new(ptr::null_mut());
+
+/// The general purpose size-class caches, used by [`allocate`] and [`deallocate`].
+static SIZE_CLASSES: [SlabCache; 9] = [
+    SlabCache::new("size-8", 8, 8),
+    SlabCache::new("size-16", 16, 16),
+    SlabCache::new("size-32", 32, 32),
+    SlabCache::new("size-64", 64, 64),
+    SlabCache::new("size-128", 128, 128),
+    SlabCache::new("size-256", 256, 256),
+    SlabCache::new("size-512", 512, 512),
+    SlabCache::new("size-1024", 1024, 1024),
+    SlabCache::new("size-2048", 2048, 2048),
+];
+
+/// Bookkeeping stored at the start of every slab.
+struct SlabHeader {
+    /// The cache this slab belongs to.
+    cache: *const SlabCache,
+    /// The next slab in the cache's list of slabs with free objects.
+    next: Option<NonNull<SlabHeader>>,
+    /// The first free object in this slab.
+    free: Option<NonNull<FreeObject>>,
+    /// Number of objects in this slab that are allocated.
+    in_use: usize,
+}
+
+/// A free object, which points to the next free object in the same slab.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// The mutable state of a [`SlabCache`].
+struct CacheInner {
+    /// Slabs with at least one free object.
+    partial: Option<NonNull<SlabHeader>>,
+    /// Number of slabs owned by the cache.
+    slabs: usize,
+    /// Number of objects currently allocated.
+    in_use: usize,
+    /// Number of allocations made from the cache, ever.
+    allocations: usize,
+    /// Number of objects returned to the cache, ever.
+    frees: usize,
+}
+
+// Safety: The slabs pointed to are only ever accessed with the cache's lock held.
+unsafe impl Send for CacheInner {}
+
+/// Usage statistics for a [`SlabCache`].
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    /// Size of each object (including padding), in bytes.
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    /// Number of objects currently allocated.
+    pub in_use: usize,
+    /// Number of allocations made from the cache, ever.
+    pub allocations: usize,
+    /// Number of objects returned to the cache, ever.
+    pub frees: usize,
+}
+
+/// A cache of equally sized objects, allocated from 4K slabs.
+pub struct SlabCache {
+    name: &'static str,
+    /// Distance between the starts of two consecutive objects in a slab.
+    stride: usize,
+    /// Offset of the first object from the start of a slab.
+    first_offset: usize,
+    /// Whether the cache has been added to [`CACHES`].
+    registered: AtomicBool,
+    /// The cache added to [`CACHES`] before this one.
+    next_cache: AtomicPtr<SlabCache>,
+    inner: Mutex<CacheInner>,
+}
+
+impl SlabCache {
+    /// Create a cache for objects of `size` bytes, aligned to `align`.
+    ///
+    /// # Panics
+    ///
+    /// * If `align` isn't a power of two.
+    /// * If an object doesn't fit in a slab alongside the slab's header.
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        let align = if align < mem::align_of::<FreeObject>() {
+            mem::align_of::<FreeObject>()
+        } else {
+            align
+        };
+        let size = if size < mem::size_of::<FreeObject>() {
+            mem::size_of::<FreeObject>()
+        } else {
+            size
+        };
+
+        let stride = align_up(size, align);
+        let first_offset = align_up(mem::size_of::<SlabHeader>(), align);
+        assert!(
+            first_offset + stride <= Size4K::SIZE,
+            "Slab objects must fit in a single frame"
+        );
+
+        Self {
+            name,
+            stride,
+            first_offset,
+            registered: AtomicBool::new(false),
+            next_cache: AtomicPtr::new(ptr::null_mut()),
+            inner: Mutex::new(CacheInner {
+                partial: None,
+                slabs: 0,
+                in_use: 0,
+                allocations: 0,
+                frees: 0,
+            }),
+        }
+    }
+
+    /// Returns the number of objects that fit in each slab.
+    pub fn objects_per_slab(&self) -> usize {
+        (Size4K::SIZE - self.first_offset) / self.stride
+    }
+
+    /// Allocate an (uninitialised) object, adding a new slab to the cache if needed. Returns
+    /// `None` if a new slab was needed, but there are no frames left or memory management isn't
+    /// set up yet.
+    pub fn allocate(&'static self) -> Option<NonNull<u8>> {
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            self.register();
+        }
+
+        self.with_inner(|inner| {
+            let mut slab = match inner.partial {
+                Some(slab) => slab,
+                None => {
+                    let slab = self.new_slab()?;
+                    inner.partial = Some(slab);
+                    inner.slabs += 1;
+                    slab
+                }
+            };
+
+            // Safety: Slabs on the partial list are mapped, and owned by this cache.
+            let header = unsafe { slab.as_mut() };
+            let object = header
+                .free
+                .expect("Slab on the partial list has no free objects");
+            // Safety: Free objects always contain a valid `FreeObject`.
+            header.free = unsafe { object.as_ref().next };
+            header.in_use += 1;
+            if header.free.is_none() {
+                // The slab is full, so take it off the partial list
+                inner.partial = header.next.take();
+            }
+
+            inner.in_use += 1;
+            inner.allocations += 1;
+            Some(object.cast())
+        })
+    }
+
+    /// Return an object to the cache.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`allocate`](Self::allocate) on this cache, and must not
+    /// be used again afterwards.
+    ///
+    /// # Panics
+    ///
+    /// * If `ptr` isn't inside one of this cache's slabs.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>) {
+        self.with_inner(|inner| {
+            let slab_addr = VirtAddr::from_ptr(ptr.as_ptr()).align_down(Size4K::SIZE);
+            let mut slab = NonNull::new_unchecked(slab_addr.as_mut_ptr::<SlabHeader>());
+            let header = slab.as_mut();
+            assert!(
+                ptr::eq(header.cache, self),
+                "{:p} doesn't belong to the {} cache",
+                ptr,
+                self.name
+            );
+
+            let was_full = header.free.is_none();
+            let object = ptr.cast::<FreeObject>();
+            object.as_ptr().write(FreeObject { next: header.free });
+            header.free = Some(object);
+            header.in_use -= 1;
+            if was_full {
+                header.next = inner.partial;
+                inner.partial = Some(slab);
+            }
+
+            inner.in_use -= 1;
+            inner.frees += 1;
+        })
+    }
+
+    /// Unmap every slab with no allocated objects, and return its frame to the frame allocator.
+    /// Returns the number of slabs released, or `None` if the kernel's frame allocator can't
+    /// deallocate frames (in which case the slabs are kept).
+    ///
+    /// This locks the memory controller (after the cache), so it must not be called with
+    /// [`controller`](super::controller) locked.
+    pub fn shrink(&self) -> Option<usize> {
+        if !<KernelFrameAllocator as FrameAllocator<Size4K>>::CAN_DEALLOCATE {
+            return None;
+        }
+
+        self.with_inner(|inner| {
+            let mut released = 0;
+
+            let mut link = &mut inner.partial;
+            while let Some(mut slab) = *link {
+                // Safety: Slabs on the partial list are mapped, and owned by this cache.
+                let header = unsafe { slab.as_mut() };
+                if header.in_use == 0 {
+                    *link = header.next;
+                    let mut memory = super::controller();
+                    let MemoryController {
+                        active_table,
+                        frame_allocator,
+                    } = &mut *memory;
+                    let frame = active_table.unmap(Page::<Size4K>::containing_addr(
+                        VirtAddr::from_ptr(slab.as_ptr()),
+                    ));
+                    frame_allocator.deallocate(frame);
+                    released += 1;
+                } else {
+                    link = &mut header.next;
+                }
+            }
+
+            inner.slabs -= released;
+            Some(released)
+        })
+    }
+
+    /// Returns the current usage statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.with_inner(|inner| CacheStats {
+            name: self.name,
+            object_size: self.stride,
+            objects_per_slab: self.objects_per_slab(),
+            slabs: inner.slabs,
+            in_use: inner.in_use,
+            allocations: inner.allocations,
+            frees: inner.frees,
+        })
+    }
+
+    /// Run `f` with the cache locked. Interrupts are disabled while it runs, so an interrupt
+    /// handler that allocates can't deadlock with the code it interrupted.
+    fn with_inner<R>(&self, f: impl FnOnce(&mut CacheInner) -> R) -> R {
+        interrupts::without_interrupts(|| f(&mut self.inner.lock()))
+    }
+
+    /// Add the cache to [`CACHES`].
+    fn register(&'static self) {
+        let this = self as *const Self as *mut Self;
+        let mut head = CACHES.load(Ordering::Acquire);
+        loop {
+            self.next_cache.store(head, Ordering::Relaxed);
+            match CACHES.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Map a new slab, and set up its header and free list.
+    fn new_slab(&'static self) -> Option<NonNull<SlabHeader>> {
+        // The heap can be used before memory management is set up, in which case it falls back
+        // to its own memory
+        let memory = super::MEMORY_CONTROLLER.get()?;
+        // If the memory controller is already locked, it's by the code allocating or the code an
+        // interrupt handler interrupted, so waiting for it would deadlock. Let the heap fall back
+        // to its own memory instead.
+        let mut memory = memory.try_lock()?;
+        let addr = NEXT_SLAB.fetch_add(Size4K::SIZE, Ordering::Relaxed);
+        assert!(
+            addr < SLAB_START + SLAB_REGION_SIZE,
+            "Slab region exhausted"
+        );
+
+        let MemoryController {
+            active_table,
+            frame_allocator,
+        } = &mut *memory;
+        let frame = FrameAllocator::<Size4K>::allocate(frame_allocator)?;
+        active_table.map_to(
+            Page::containing_addr(VirtAddr::new(addr)),
+            frame,
+            EntryFlags::WRITABLE | EntryFlags::NO_EXEC,
+            frame_allocator,
+        );
+        drop(memory);
+
+        let mut free = None;
+        for i in (0..self.objects_per_slab()).rev() {
+            let object = (addr + self.first_offset + i * self.stride) as *mut FreeObject;
+            // Safety: The object lies inside the slab, which was just mapped.
+            unsafe { object.write(FreeObject { next: free }) };
+            free = NonNull::new(object);
+        }
+
+        let header = addr as *mut SlabHeader;
+        // Safety: The header lies at the start of the slab, which was just mapped.
+        unsafe {
+            header.write(SlabHeader {
+                cache: self,
+                next: None,
+                free,
+                in_use: 0,
+            })
+        };
+        NonNull::new(header)
+    }
+}
+
+/// A named cache for objects of type `T`.
+#[allow(dead_code)]
+pub struct ObjectCache<T> {
+    cache: SlabCache,
+    _marker: PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<T> ObjectCache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            cache: SlabCache::new(name, mem::size_of::<T>(), mem::align_of::<T>()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Move `value` into an object allocated from the cache. If there's no memory left, `value`
+    /// is given back.
+    pub fn alloc(&'static self, value: T) -> Result<SlabBox<T>, T> {
+        match self.cache.allocate() {
+            Some(ptr) => {
+                let ptr = ptr.cast::<T>();
+                // Safety: The object is suitably sized and aligned for a `T`.
+                unsafe { ptr.as_ptr().write(value) };
+                Ok(SlabBox { ptr, cache: self })
+            }
+            None => Err(value),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// See [`SlabCache::shrink`].
+    pub fn shrink(&self) -> Option<usize> {
+        self.cache.shrink()
+    }
+}
+
+/// An owned `T` allocated from an [`ObjectCache`], which is returned to the cache when dropped.
+#[allow(dead_code)]
+pub struct SlabBox<T: 'static> {
+    ptr: NonNull<T>,
+    cache: &'static ObjectCache<T>,
+}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: The object was initialised in `ObjectCache::alloc`, and is owned by us.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The object was initialised in `ObjectCache::alloc`, and is owned by us.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        // Safety: The object is owned by us, was allocated from this cache, and is never used
+        // again.
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.cache.cache.deallocate(self.ptr.cast());
+        }
+    }
+}
+
+/// Returns the size-class cache that can hold objects with `layout`, if there is one.
+fn size_class(layout: Layout) -> Option<&'static SlabCache> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().find(|cache| cache.stride >= size)
+}
+
+/// Allocate an object with `layout` from the smallest size-class cache that can hold it. Returns
+/// `None` if the object is larger than 2 kibibytes, or there is no memory left.
+pub fn allocate(layout: Layout) -> Option<NonNull<u8>> {
+    size_class(layout)?.allocate()
+}
+
+/// Returns whether `ptr` lies in the slab region, I.E. was allocated from a cache.
+pub fn contains(ptr: *const u8) -> bool {
+    (SLAB_START..SLAB_START + SLAB_REGION_SIZE).contains(&(ptr as usize))
+}
+
+/// Return an object to its size-class cache.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`allocate`] with the same `layout`, and must not be used
+/// again afterwards.
+pub unsafe fn deallocate(ptr: NonNull<u8>, layout: Layout) {
+    size_class(layout)
+        .expect("Layout is too large for a slab")
+        .deallocate(ptr);
+}
+
+/// Calls `f` with every cache that has been used so far, most recently added first.
+fn for_each_cache(mut f: impl FnMut(&'static SlabCache)) {
+    let mut cache = CACHES.load(Ordering::Acquire);
+    // Safety: Only `'static` caches are added to the list, and they're never removed
+    while let Some(current) = unsafe { cache.as_ref() } {
+        f(current);
+        cache = current.next_cache.load(Ordering::Relaxed);
+    }
+}
+
+/// Release the empty slabs of every cache that has been used so far. Returns the number of slabs
+/// released, or `None` if the kernel's frame allocator can't deallocate frames.
+pub fn shrink_all() -> Option<usize> {
+    let mut released = 0;
+    for_each_cache(|cache| released += cache.shrink().unwrap_or(0));
+    <KernelFrameAllocator as FrameAllocator<Size4K>>::CAN_DEALLOCATE.then_some(released)
+}
+
+/// Print the usage statistics of every cache that has been used so far.
+pub fn print_stats() {
+    println!(
+        "{:20} {:>6} {:>6} {:>6} {:>8} {:>10} {:>10}",
+        "Cache", "Size", "Slabs", "Per", "In use", "Allocs", "Frees"
+    );
+    for_each_cache(|cache| {
+        let stats = cache.stats();
+        println!(
+            "{:20} {:>6} {:>6} {:>6} {:>8} {:>10} {:>10}",
+            stats.name,
+            stats.object_size,
+            stats.slabs,
+            stats.objects_per_slab,
+            stats.in_use,
+            stats.allocations,
+            stats.frees
+        );
+    });
+}
+
+/// Align `value` upwards to `align`, which must be a power of two.
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+
+    #[test_case]
+    fn size_classes() {
+        let class = |size, align| {
+            size_class(Layout::from_size_align(size, align).unwrap()).map(|cache| cache.stride)
+        };
+        assert_eq!(class(1, 1), Some(8));
+        assert_eq!(class(8, 8), Some(8));
+        assert_eq!(class(9, 1), Some(16));
+        // Over-aligned objects go in a class at least as large as their alignment
+        assert_eq!(class(4, 64), Some(64));
+        assert_eq!(class(2048, 8), Some(2048));
+        assert_eq!(class(2049, 8), None);
+    }
+
+    #[test_case]
+    fn slab_layout() {
+        // Objects have to be large enough to hold a free list link
+        let cache = SlabCache::new("test-tiny", 1, 1);
+        assert_eq!(cache.stride, mem::size_of::<FreeObject>());
+
+        let cache = SlabCache::new("test-aligned", 24, 64);
+        assert_eq!(cache.stride, 64);
+        assert_eq!(cache.first_offset, 64);
+        assert_eq!(cache.objects_per_slab(), 63);
+    }
+
+    #[test_case]
+    fn allocate_and_deallocate() {
+        static CACHE: SlabCache = SlabCache::new("test-allocate", 48, 16);
+        let count = CACHE.objects_per_slab() + 1;
+
+        // Fill one slab, and start another
+        let objects: Vec<_> = (0..count).map(|_| CACHE.allocate().unwrap()).collect();
+        let mut addrs: Vec<_> = objects
+            .iter()
+            .map(|object| object.as_ptr() as usize)
+            .collect();
+        addrs.sort_unstable();
+        assert!(addrs
+            .iter()
+            .all(|&addr| contains(addr as _) && addr % 16 == 0));
+        assert!(addrs.windows(2).all(|pair| pair[1] - pair[0] >= 48));
+        let stats = CACHE.stats();
+        assert_eq!((stats.slabs, stats.in_use), (2, count));
+
+        for &object in &objects {
+            // Safety: The object came from this cache, and isn't used again
+            unsafe { CACHE.deallocate(object) };
+        }
+        let stats = CACHE.stats();
+        assert_eq!(
+            (stats.in_use, stats.allocations, stats.frees),
+            (0, count, count)
+        );
+
+        // Freed objects are reused, rather than mapping another slab
+        let object = CACHE.allocate().unwrap();
+        assert!(objects.contains(&object));
+        assert_eq!(CACHE.stats().slabs, 2);
+        // Safety: The object came from this cache, and isn't used again
+        unsafe { CACHE.deallocate(object) };
+    }
+
+    #[test_case]
+    fn shrink_releases_empty_slabs() {
+        static CACHE: SlabCache = SlabCache::new("test-shrink", 512, 8);
+        let full: Vec<_> = (0..CACHE.objects_per_slab())
+            .map(|_| CACHE.allocate().unwrap())
+            .collect();
+        let kept = CACHE.allocate().unwrap();
+        for object in full {
+            // Safety: The object came from this cache, and isn't used again
+            unsafe { CACHE.deallocate(object) };
+        }
+
+        if <KernelFrameAllocator as FrameAllocator<Size4K>>::CAN_DEALLOCATE {
+            assert_eq!(CACHE.shrink(), Some(1));
+            assert_eq!(CACHE.stats().slabs, 1);
+        } else {
+            assert_eq!(CACHE.shrink(), None);
+            assert_eq!(CACHE.stats().slabs, 2);
+        }
+        // Safety: The object came from this cache, and isn't used again
+        unsafe { CACHE.deallocate(kept) };
+    }
+
+    #[test_case]
+    fn slab_box_drops_its_value() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted(u64);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        static CACHE: ObjectCache<Counted> = ObjectCache::new("test-counted");
+
+        let mut object = CACHE
+            .alloc(Counted(1))
+            .unwrap_or_else(|_| panic!("Out of memory"));
+        object.0 += 1;
+        assert_eq!(object.0, 2);
+        assert_eq!(CACHE.stats().in_use, 1);
+
+        drop(object);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        assert_eq!(CACHE.stats().in_use, 0);
+    }
+
+    #[test_case]
+    fn no_slabs_mapped_with_controller_locked() {
+        static CACHE: SlabCache = SlabCache::new("test-locked", 128, 8);
+        let memory = super::super::controller();
+        assert!(CACHE.allocate().is_none());
+        // The heap falls back to its own memory, rather than deadlocking
+        let boxed = Box::new([0_u8; 100]);
+        drop(boxed);
+        drop(memory);
+
+        let object = CACHE.allocate().unwrap();
+        // Safety: The object came from this cache, and isn't used again
+        unsafe { CACHE.deallocate(object) };
+    }
+
+    #[test_case]
+    fn heap_uses_size_classes() {
+        let small = Box::new(0_u64);
+        assert!(contains(&*small as *const u64 as *const u8));
+        let large = vec![0_u8; 4096];
+        assert!(!contains(large.as_ptr()));
+    }
+}
@@ -1,17 +1,20 @@
 pub mod serial;
 pub mod vga;
 
-/// Print to the VGA text buffer and over the first serial port.
+/// Print to the VGA text buffer and over the first serial port, and record it in the kernel
+/// message buffer.
 #[macro_export]
 
 macro_rules! print {
     ($($arg: tt)*) => {
         $crate::output::vga::_print(format_args!($($arg)*));
         $crate::output::serial::_print(format_args!($($arg)*));
+        $crate::dmesg::_print(format_args!($($arg)*));
     };
 }
 
-/// Print to the VGA text buffer and over the first serial port, appending a newline.
+/// Print to the VGA text buffer and over the first serial port, and record it in the kernel
+/// message buffer, appending a newline.
 #[macro_export]
 
 macro_rules! println {
@@ -20,3 +23,22 @@ macro_rules! println {
     ($fmt: expr, $($arg: tt)*) => ($crate::print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+/// Print over the first serial port only.
+#[macro_export]
+
+macro_rules! serial_print {
+    ($($arg: tt)*) => {
+        $crate::output::serial::_print(format_args!($($arg)*));
+    };
+}
+
+/// Print over the first serial port only, appending a newline.
+#[macro_export]
+
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt: expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt: expr, $($arg: tt)*) => ($crate::serial_print!(
+        concat!($fmt, "\n"), $($arg)*));
+}
@@ -2,23 +2,169 @@ use core::fmt::{self, Write};
 
 use spin::{Lazy, Mutex};
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+use crate::{interrupts::irq, ring_buffer::RingBuffer};
+
+/// Offset of the line status register from a serial port's base IO address.
+
+const LINE_STATUS: u16 = 5;
+
+/// Offset of the scratch register from a serial port's base IO address.
+
+const SCRATCH: u16 = 7;
+
+/// Line status bit set when a received byte is waiting to be read.
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+
+/// A serial port, with a buffer of the bytes received on it.
+
+pub struct SerialDevice {
+    base: u16,
+    irq: u8,
+    present: bool,
+    port: Mutex<SerialPort>,
+    rx: RingBuffer<u8, 256>,
+}
+
+impl SerialDevice {
+    fn new(base: u16, irq: u8) -> Self {
+        let mut port = unsafe { SerialPort::new(base) };
+
+        // A missing port reads as all ones, so check that the scratch register holds a value
+        let mut scratch = Port::<u8>::new(base + SCRATCH);
+        let present = unsafe {
+            scratch.write(0x5a);
+            scratch.read() == 0x5a
+        };
+
+        if present {
+            // This also enables the receive interrupt
+            port.init();
+        }
+
+        Self {
+            base,
+            irq,
+            present,
+            port: Mutex::new(port),
+            rx: RingBuffer::new(),
+        }
+    }
+
+    /// Returns whether the port exists.
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Write formatted text to the port.
+
+    pub fn write_fmt(&self, args: fmt::Arguments) {
+        if self.present {
+            self.port.lock().write_fmt(args).unwrap();
+        }
+    }
+
+    /// Write a byte to the port, without any translation.
+
+    #[allow(dead_code)]
+    pub fn write_byte(&self, byte: u8) {
+        if self.present {
+            self.port.lock().send_raw(byte);
+        }
+    }
+
+    /// Returns the next byte received, if there is one. Only one context may read from a port at
+    /// a time.
+
+    pub fn read_byte(&self) -> Option<u8> {
+        self.rx.pop()
+    }
+
+    /// Returns the next byte received, if there is one, checking the port directly instead of
+    /// relying on its IRQ. For use with interrupts disabled, E.G. from an exception handler.
+
+    pub fn poll_byte(&self) -> Option<u8> {
+        self.receive();
+        self.read_byte()
+    }
+
+    /// Move any bytes the port has received into the buffer.
+
+    fn receive(&self) {
+        if !self.present {
+            return;
+        }
+        let mut line_status = Port::<u8>::new(self.base + LINE_STATUS);
+        let mut data = Port::<u8>::new(self.base);
+        while unsafe { line_status.read() } & LINE_STATUS_DATA_READY != 0 {
+            // Drop bytes if nobody is reading them
+            let _ = self.rx.push(unsafe { data.read() });
+        }
+    }
+}
 
 /// Base IO address of the first serial port.
 
-const IO_BASE: u16 = 0x3F8;
+const COM1_BASE: u16 = 0x3f8;
+
+/// The first serial port, used for kernel output.
+
+pub static COM1: Lazy<SerialDevice> = Lazy::new(|| SerialDevice::new(COM1_BASE, 4));
+
+/// The second serial port.
+
+pub static COM2: Lazy<SerialDevice> = Lazy::new(|| SerialDevice::new(0x2f8, 3));
+
+/// The third serial port, which shares an IRQ with the first.
+
+pub static COM3: Lazy<SerialDevice> = Lazy::new(|| SerialDevice::new(0x3e8, 4));
 
-/// An interface to the first serial port.
+/// The fourth serial port, which shares an IRQ with the second.
 
-static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
-    let mut sp = unsafe { SerialPort::new(IO_BASE) };
+pub static COM4: Lazy<SerialDevice> = Lazy::new(|| SerialDevice::new(0x2e8, 3));
 
-    sp.init();
+static DEVICES: [&Lazy<SerialDevice>; 4] = [&COM1, &COM2, &COM3, &COM4];
 
-    Mutex::new(sp)
-});
+/// Returns serial port `n` (1-4), if it exists.
+
+#[allow(dead_code)]
+pub fn device(n: usize) -> Option<&'static SerialDevice> {
+    let device: &'static SerialDevice = DEVICES.get(n.checked_sub(1)?)?;
+    device.is_present().then_some(device)
+}
+
+/// Detect the serial ports, and start receiving on the ones that exist.
+
+pub fn init() {
+    for device in DEVICES.iter() {
+        if device.is_present() {
+            irq::register_irq(device.irq, serial_irq);
+        }
+    }
+}
+
+fn serial_irq(irq: u8) {
+    for device in DEVICES.iter().filter(|device| device.irq == irq) {
+        device.receive();
+    }
+}
+
+/// Access the first serial port without waiting for its lock, E.G. when panicking, as whatever
+/// holds the lock may never release it.
+///
+/// # Safety
+///
+/// Nothing else may write to the port while the returned handle is used.
+
+pub unsafe fn panic_port() -> SerialPort {
+    SerialPort::new(COM1_BASE)
+}
 
 #[doc(hidden)]
 
 pub fn _print(args: fmt::Arguments) {
-    SERIAL1.lock().write_fmt(args).unwrap();
+    COM1.write_fmt(args);
 }
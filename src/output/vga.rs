@@ -12,6 +12,18 @@ pub fn _print(args: fmt::Arguments) {
     WRITER.lock().write_fmt(args).unwrap();
 }
 
+#[doc(hidden)]
+
+pub fn _print_colored(fg: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let mut writer = WRITER.lock();
+    let color = writer.color;
+    writer.color = ColorCode::new(fg, Color::Black);
+    writer.write_fmt(args).unwrap();
+    writer.color = color;
+}
+
 /// A VGA color.
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -189,6 +201,17 @@ impl Writer {
     pub fn write_byte(&mut self, c: u8) {
         match c {
             b'\n' => self.new_line(),
+            // Backspace erases the character before the cursor, without leaving the line
+            0x08 => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    let ptr = self.buff.mut_ptr_at(self.row, self.col);
+
+                    unsafe {
+                        ptr.write_volatile(ScreenChar::new(b' ', self.color));
+                    }
+                }
+            }
             c => {
                 let ptr = self.buff.mut_ptr_at(self.row, self.col);
 
@@ -211,7 +234,7 @@ impl Writer {
         for &byte in bytes.as_ref() {
             match byte {
                 // printable
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                0x20..=0x7e | b'\n' | 0x08 => self.write_byte(byte),
                 // not printable
                 _ => self.write_byte(0xfe),
             }
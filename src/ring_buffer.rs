@@ -0,0 +1,67 @@
+//! A fixed-size, lock-free ring buffer, for passing data from interrupt handlers to the rest of the
+//! kernel.
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A lock-free single-producer, single-consumer queue holding up to `N - 1` items.
+///
+/// Only one context may push at a time (normally an interrupt handler), and only one may pop at a
+/// time. Neither ever blocks, so pushing from an interrupt handler can't deadlock with a reader it
+/// interrupted.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Index of the next slot to pop from.
+    head: AtomicUsize,
+    /// Index of the next slot to push to.
+    tail: AtomicUsize,
+}
+
+// Safety: Slots are only written by the producer before publishing them through `tail`, and only
+// read by the consumer before releasing them through `head`.
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add `item` to the back of the queue, or return it if the queue is full.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        // Safety: The slot isn't visible to the consumer until `tail` is updated
+        unsafe { (*self.slots.get())[tail] = MaybeUninit::new(item) };
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Remove the item at the front of the queue, if there is one.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // Safety: The slot was initialised by `push` before `tail` was moved past it, and won't be
+        // overwritten until `head` is moved past it
+        let item = unsafe { (*self.slots.get())[head].assume_init() };
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+
+    /// Returns whether the queue is empty.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
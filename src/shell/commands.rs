@@ -0,0 +1,187 @@
+//! The shell's built-in commands.
+
+use x86_64::instructions::{interrupts, tables};
+
+use super::register_command;
+use crate::{
+    input::ps2,
+    interrupts::{apic, exceptions::Exception, pic},
+    memory::{self, paging::TableWalk, slab, SizedRegion, VirtAddr},
+    println,
+    time::{self, clock, rtc},
+};
+
+/// Bit of an IDT gate's options set if the gate is present.
+const GATE_PRESENT: u16 = 1 << 15;
+
+pub(super) fn register_builtins() {
+    register_command("help", "List the available commands", help);
+    register_command(
+        "meminfo",
+        "Show the memory map, free frames and heap usage",
+        meminfo,
+    );
+    register_command(
+        "slabinfo",
+        "slabinfo [shrink]: Show slab cache usage, or release empty slabs",
+        slabinfo,
+    );
+    register_command("elf", "Show the kernel's ELF sections", elf);
+    register_command("pt", "pt <addr>: Walk the page tables for an address", pt);
+    register_command("idt", "List the interrupt handlers in the IDT", idt);
+    register_command("uptime", "Show the time since boot, and the date", uptime);
+    register_command("reboot", "Restart the machine", reboot);
+    register_command("halt", "Stop the machine", halt);
+}
+
+fn help(_args: &[&str]) {
+    for command in super::commands() {
+        println!("{:10} {}", command.name, command.help);
+    }
+}
+
+fn meminfo(_args: &[&str]) {
+    crate::init::print_memory_areas(crate::init::boot_info());
+
+    let free_frames = memory::controller().frame_allocator.free_frames();
+    println!(
+        "{} free frames ({} KiB)",
+        free_frames,
+        free_frames * memory::Size4K::SIZE / 1024
+    );
+
+    let heap = memory::heap::stats();
+    println!(
+        "Kernel heap: {} bytes used, {} bytes free, {} bytes total",
+        heap.used, heap.free, heap.size
+    );
+}
+
+fn slabinfo(args: &[&str]) {
+    match args {
+        [] => slab::print_stats(),
+        ["shrink"] => match slab::shrink_all() {
+            Some(released) => {
+                println!("Released {} empty slabs", released);
+            }
+            None => {
+                println!("The frame allocator can't free frames, so slabs can't be released");
+            }
+        },
+        _ => {
+            println!("Usage: slabinfo [shrink]");
+        }
+    }
+}
+
+fn elf(_args: &[&str]) {
+    crate::init::print_elf_sections(crate::init::boot_info());
+}
+
+fn pt(args: &[&str]) {
+    let addr = match args {
+        [addr] => usize::from_str_radix(addr.trim_start_matches("0x"), 16),
+        _ => {
+            println!("Usage: pt <hex address>");
+            return;
+        }
+    };
+    match addr {
+        // Only canonical addresses can be walked
+        Ok(addr) => match VirtAddr::try_new(addr) {
+            Some(addr) => {
+                println!("{}", TableWalk::new(addr));
+            }
+            None => {
+                println!("{:#x} isn't a canonical address", addr);
+            }
+        },
+        Err(e) => {
+            println!("Invalid address: {}", e);
+        }
+    }
+}
+
+/// A description of what handles interrupt vector `vector`.
+fn vector_name(vector: u8) -> &'static str {
+    const IRQ_NAMES: [&str; 16] = [
+        "IRQ 0", "IRQ 1", "IRQ 2", "IRQ 3", "IRQ 4", "IRQ 5", "IRQ 6", "IRQ 7", "IRQ 8", "IRQ 9",
+        "IRQ 10", "IRQ 11", "IRQ 12", "IRQ 13", "IRQ 14", "IRQ 15",
+    ];
+    if let Some(exception) = Exception::from_vector(vector) {
+        exception.name()
+    } else if (pic::IRQ_BASE..pic::IRQ_BASE + pic::IRQ_COUNT).contains(&vector) {
+        IRQ_NAMES[(vector - pic::IRQ_BASE) as usize]
+    } else if vector == apic::SPURIOUS_VECTOR {
+        "APIC spurious interrupt"
+    } else if vector == clock::TIMER_VECTOR {
+        "APIC timer"
+    } else if vector == pic::DISABLED_IRQ_BASE + 7 {
+        "Disabled PIC spurious interrupt"
+    } else {
+        ""
+    }
+}
+
+fn idt(_args: &[&str]) {
+    // Read the gates from the IDT that is actually loaded
+    let idtr = tables::sidt();
+    let count = (idtr.limit as usize + 1) / 16;
+    let gates = idtr.base.as_ptr::<[u16; 8]>();
+
+    println!("{:6} {:18} {:3} Name", "Vector", "Handler", "IST");
+    for vector in 0..count.min(256) {
+        // Safety: The IDTR describes the loaded IDT, which is always mapped.
+        let gate = unsafe { gates.add(vector).read() };
+        let options = gate[2];
+        if options & GATE_PRESENT == 0 {
+            continue;
+        }
+        let handler = gate[0] as u64
+            | (gate[3] as u64) << 16
+            | (gate[4] as u64) << 32
+            | (gate[5] as u64) << 48;
+        println!(
+            "{:#6x} {:#018x} {:3} {}",
+            vector,
+            handler,
+            options & 0b111,
+            vector_name(vector as u8)
+        );
+    }
+}
+
+fn uptime(_args: &[&str]) {
+    let uptime = clock::now();
+    println!(
+        "Up {}.{:03} seconds ({} ticks at {} Hz, {:?} clock)",
+        uptime.as_secs(),
+        uptime.subsec_millis(),
+        time::ticks(),
+        time::frequency(),
+        clock::source()
+    );
+    println!("The time is {}", rtc::wall_clock().date);
+}
+
+fn reboot(_args: &[&str]) {
+    println!("Rebooting...");
+    interrupts::disable();
+    ps2::pulse_reset();
+
+    // If that didn't work, triple fault by raising an exception with an empty IDT
+    let empty = tables::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::zero(),
+    };
+    unsafe {
+        tables::lidt(&empty);
+        core::arch::asm!("int3", options(noreturn));
+    }
+}
+
+fn halt(_args: &[&str]) {
+    println!("Halted");
+    interrupts::disable();
+    crate::hlt_loop();
+}
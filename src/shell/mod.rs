@@ -0,0 +1,122 @@
+//! A simple command shell for inspecting the kernel, driven from the keyboard or the first serial
+//! port.
+
+mod commands;
+
+use alloc::{string::String, vec::Vec};
+
+use spin::Mutex;
+
+use crate::{
+    input::{keyboard, line},
+    output::serial,
+    print, println,
+};
+
+/// A function running a shell command, given its arguments (not including the command's name).
+pub type CommandFn = fn(args: &[&str]);
+
+/// A command that can be run from the shell.
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    /// A one-line description, shown by `help`.
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+static COMMANDS: Mutex<Vec<Command>> = Mutex::new(Vec::new());
+
+/// Make `run` available as the shell command `name`, returning the command it replaced, if any.
+///
+/// # Panics
+///
+/// * If `name` is empty or contains whitespace.
+pub fn register_command(name: &'static str, help: &'static str, run: CommandFn) -> Option<Command> {
+    assert!(
+        !name.is_empty() && !name.contains(char::is_whitespace),
+        "Invalid shell command name {:?}",
+        name
+    );
+    let command = Command { name, help, run };
+    let mut commands = COMMANDS.lock();
+    match commands.iter_mut().find(|command| command.name == name) {
+        Some(old) => Some(core::mem::replace(old, command)),
+        None => {
+            commands.push(command);
+            None
+        }
+    }
+}
+
+/// Remove the shell command `name`, returning it.
+#[allow(dead_code)]
+pub fn unregister_command(name: &str) -> Option<Command> {
+    let mut commands = COMMANDS.lock();
+    let index = commands.iter().position(|command| command.name == name)?;
+    Some(commands.remove(index))
+}
+
+/// Returns the commands currently registered, in the order they were registered.
+pub fn commands() -> Vec<Command> {
+    COMMANDS.lock().clone()
+}
+
+/// Wait for a character from the keyboard or the first serial port.
+fn read_char() -> char {
+    loop {
+        if let Some(c) = keyboard::read_char() {
+            return c;
+        }
+        if let Some(byte) = serial::COM1.read_byte() {
+            return byte as char;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Read a line of input from the keyboard or the first serial port, echoing it to both the
+/// screen and the serial port.
+fn read_line() -> String {
+    line::read_line(read_char, |c| {
+        print!("{}", c);
+    })
+}
+
+/// Run the command in `line`, if there is one.
+fn execute(line: &str) {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return,
+    };
+    let args: Vec<&str> = words.collect();
+
+    // Copy the command out, so the lock isn't held while it runs
+    let command = COMMANDS
+        .lock()
+        .iter()
+        .find(|command| command.name == name)
+        .copied();
+    match command {
+        Some(command) => (command.run)(&args),
+        None => {
+            println!("{}: command not found (try 'help')", name);
+        }
+    }
+}
+
+/// Register the built-in commands. This is done before the other subsystems are initialised, so
+/// that they can replace the built-in commands with their own.
+pub fn init() {
+    commands::register_builtins();
+}
+
+/// Run the shell forever. Interrupts must be enabled, so that input can be received.
+pub fn run() -> ! {
+    println!("Kernel debug shell. Type 'help' for a list of commands.");
+    loop {
+        print!("rux> ");
+        execute(&read_line());
+    }
+}
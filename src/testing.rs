@@ -0,0 +1,62 @@
+//! The kernel's test framework, used by `make test`. Tests are functions marked `#[test_case]`,
+//! which pass if they return and fail if they panic. They run in QEMU once the kernel is
+//! initialised, reporting their results over the first serial port, and then QEMU is shut down
+//! through its `isa-debug-exit` device with a [`QemuExitCode`] saying whether they all passed.
+
+use core::{any::type_name, panic::PanicInfo};
+
+use x86_64::instructions::port::Port;
+
+use crate::{hlt_loop, serial_print, serial_println};
+
+/// IO port of QEMU's `isa-debug-exit` device, as configured by `test-runner.sh`.
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// The value written to the `isa-debug-exit` device. QEMU exits with `(code << 1) | 1`, so
+/// neither can be confused with QEMU's own exit codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Shut down QEMU, making it exit with `code`.
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    // Safety: The port belongs to the isa-debug-exit device, which only exits QEMU
+    unsafe { Port::new(ISA_DEBUG_EXIT_PORT).write(code as u32) };
+    // Not running in QEMU (or the device is missing)
+    hlt_loop();
+}
+
+/// A test, printed with its name as it runs.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{} ... ", type_name::<T>());
+        self();
+        serial_println!("ok");
+    }
+}
+
+/// Run each of `tests`, stopping at the first failure.
+pub fn runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    serial_println!("All tests passed");
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// The panic handler used while testing, which fails the running test.
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("FAILED");
+    serial_println!("Kernel {}", info);
+    crate::backtrace::print_backtrace();
+    crate::dmesg::flush_to_serial();
+    exit_qemu(QemuExitCode::Failed);
+}
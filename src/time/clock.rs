@@ -0,0 +1,242 @@
+//! High resolution monotonic time from the TSC, and deadlines from the local APIC timer, both
+//! calibrated against the PIT.
+//!
+//! If the TSC isn't invariant (so its rate changes with the CPU frequency), time comes from the
+//! PIT ticks instead. If there's no local APIC, deadlines are checked on every PIT tick.
+
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use log::info;
+use spin::Mutex;
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame};
+
+use super::NANOS_PER_SEC;
+use crate::interrupts::apic::{self, Register, LVT_MASKED};
+
+/// Vector of the local APIC timer interrupt.
+pub const TIMER_VECTOR: u8 = 0xf0;
+
+/// Number of PIT ticks to calibrate against.
+const CALIBRATION_TICKS: u64 = 50;
+/// Local APIC timer divide configuration value for dividing by 16.
+const LAPIC_DIVIDE_BY_16: u32 = 0b0011;
+/// Bit of the local APIC timer LVT register selecting periodic mode.
+const LAPIC_PERIODIC: u32 = 1 << 17;
+
+/// The time source used by [`now`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The invariant TSC.
+    Tsc,
+    /// The PIT tick counter.
+    Pit,
+}
+
+/// TSC frequency in Hz, or 0 if the TSC isn't used.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// TSC value at the end of calibration.
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Uptime at the end of calibration, in nanoseconds, so that [`now`] continues from the PIT.
+static TSC_BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Local APIC timer frequency (after dividing by 16) in Hz, or 0 if it isn't used.
+static LAPIC_TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// A pending deadline.
+struct Deadline {
+    handler: fn(),
+    /// The interval to re-arm the deadline with, for periodic deadlines.
+    period: Option<Duration>,
+    /// The PIT tick at which the deadline expires, when the local APIC timer isn't used.
+    pit_tick: u64,
+}
+
+static DEADLINE: Mutex<Option<Deadline>> = Mutex::new(None);
+
+fn rdtsc() -> u64 {
+    // Safety: The TSC is available on all x86_64 processors
+    unsafe { _rdtsc() }
+}
+
+/// Returns whether the TSC runs at a constant rate, regardless of CPU frequency and sleep states.
+fn tsc_is_invariant() -> bool {
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && __cpuid(0x8000_0007).edx & 1 << 8 != 0
+}
+
+/// Calibrate the TSC and local APIC timer against the PIT. Must be called after
+/// [`super::init`], with interrupts enabled.
+pub fn init() {
+    let tsc_invariant = tsc_is_invariant();
+    let lapic = apic::local_apic();
+
+    // Start on a tick boundary
+    let start_tick = super::ticks() + 1;
+    while super::ticks() < start_tick {
+        core::hint::spin_loop();
+    }
+    let tsc_start = rdtsc();
+    if let Some(lapic) = lapic {
+        lapic.write(Register::TimerDivide, LAPIC_DIVIDE_BY_16);
+        lapic.write(Register::LvtTimer, LVT_MASKED);
+        lapic.write(Register::TimerInitialCount, u32::MAX);
+    }
+
+    while super::ticks() < start_tick + CALIBRATION_TICKS {
+        core::hint::spin_loop();
+    }
+    let tsc_end = rdtsc();
+    let lapic_elapsed = lapic.map(|lapic| {
+        let elapsed = u32::MAX - lapic.read(Register::TimerCurrentCount);
+        lapic.write(Register::TimerInitialCount, 0);
+        elapsed
+    });
+    let elapsed_nanos = super::ticks_to_duration(CALIBRATION_TICKS).as_nanos();
+    let frequency = |count: u64| (count as u128 * NANOS_PER_SEC / elapsed_nanos) as u64;
+
+    if tsc_invariant {
+        TSC_BASE.store(tsc_end, Ordering::Relaxed);
+        TSC_BASE_NANOS.store(
+            super::ticks_to_duration(start_tick + CALIBRATION_TICKS).as_nanos() as u64,
+            Ordering::Relaxed,
+        );
+        TSC_FREQUENCY.store(frequency(tsc_end - tsc_start), Ordering::Release);
+        info!(
+            "Clock: invariant TSC at {} MHz",
+            frequency(tsc_end - tsc_start) / 1_000_000
+        );
+    } else {
+        info!("Clock: TSC isn't invariant, using the PIT");
+    }
+
+    if let Some(elapsed) = lapic_elapsed {
+        LAPIC_TIMER_FREQUENCY.store(frequency(elapsed as u64), Ordering::Release);
+        info!(
+            "Local APIC timer at {} kHz",
+            frequency(elapsed as u64) / 1000
+        );
+    }
+}
+
+/// Returns the time source used by [`now`].
+#[allow(dead_code)]
+pub fn source() -> ClockSource {
+    if TSC_FREQUENCY.load(Ordering::Acquire) != 0 {
+        ClockSource::Tsc
+    } else {
+        ClockSource::Pit
+    }
+}
+
+/// Returns the monotonic time since the timer was started, with nanosecond resolution if the
+/// TSC is used.
+#[allow(dead_code)]
+pub fn now() -> Duration {
+    Duration::from_nanos(monotonic_nanos())
+}
+
+/// Returns the monotonic time since the timer was started, in nanoseconds.
+pub fn monotonic_nanos() -> u64 {
+    let frequency = TSC_FREQUENCY.load(Ordering::Acquire);
+    if frequency == 0 {
+        return super::uptime().as_nanos() as u64;
+    }
+    let elapsed = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+    TSC_BASE_NANOS.load(Ordering::Relaxed)
+        + (elapsed as u128 * NANOS_PER_SEC / frequency as u128) as u64
+}
+
+/// Call `handler` (in interrupt context) once, after `delay` has passed, replacing any pending
+/// deadline.
+#[allow(dead_code)]
+pub fn set_oneshot(delay: Duration, handler: fn()) {
+    set_deadline(delay, None, handler);
+}
+
+/// Call `handler` (in interrupt context) every `period`, replacing any pending deadline.
+#[allow(dead_code)]
+pub fn set_periodic(period: Duration, handler: fn()) {
+    set_deadline(period, Some(period), handler);
+}
+
+/// Cancel the pending deadline, if any.
+#[allow(dead_code)]
+pub fn cancel_deadline() {
+    interrupts::without_interrupts(|| {
+        if let Some((_, lapic)) = lapic_timer() {
+            lapic.write(Register::TimerInitialCount, 0);
+        }
+        DEADLINE.lock().take();
+    });
+}
+
+/// Returns the local APIC timer's frequency and the local APIC, if the timer has been calibrated.
+fn lapic_timer() -> Option<(u64, &'static apic::LocalApic)> {
+    match LAPIC_TIMER_FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        frequency => Some((frequency, apic::local_apic()?)),
+    }
+}
+
+fn set_deadline(delay: Duration, period: Option<Duration>, handler: fn()) {
+    interrupts::without_interrupts(|| {
+        let mut deadline = DEADLINE.lock();
+        *deadline = Some(Deadline {
+            handler,
+            period,
+            pit_tick: super::ticks() + super::duration_to_ticks(delay),
+        });
+
+        if let Some((frequency, lapic)) = lapic_timer() {
+            let count = (delay.as_nanos() * frequency as u128 / NANOS_PER_SEC)
+                .clamp(1, u32::MAX as u128) as u32;
+            let mode = if period.is_some() { LAPIC_PERIODIC } else { 0 };
+            lapic.write(Register::TimerDivide, LAPIC_DIVIDE_BY_16);
+            lapic.write(Register::LvtTimer, mode | TIMER_VECTOR as u32);
+            lapic.write(Register::TimerInitialCount, count);
+        }
+    });
+}
+
+/// Take the handler of the expired deadline, removing the deadline unless it is periodic.
+fn expire(deadline: &mut Option<Deadline>) -> Option<fn()> {
+    let d = deadline.as_mut()?;
+    let handler = d.handler;
+    match d.period {
+        Some(period) => d.pit_tick += super::duration_to_ticks(period).max(1),
+        None => *deadline = None,
+    }
+    Some(handler)
+}
+
+/// Check for an expired deadline on PIT tick `tick`, when the local APIC timer isn't used.
+pub(super) fn pit_tick(tick: u64) {
+    if lapic_timer().is_some() {
+        return;
+    }
+    let handler = {
+        let mut deadline = DEADLINE.lock();
+        match &*deadline {
+            Some(d) if d.pit_tick <= tick => expire(&mut deadline),
+            _ => None,
+        }
+    };
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+/// Handler for the local APIC timer interrupt.
+pub(crate) extern "x86-interrupt" fn timer_interrupt_handler(_: InterruptStackFrame) {
+    // Copy the handler out, so the lock isn't held while it runs
+    let handler = expire(&mut DEADLINE.lock());
+    if let Some(handler) = handler {
+        handler();
+    }
+    if let Some(lapic) = apic::local_apic() {
+        lapic.end_of_interrupt();
+    }
+}
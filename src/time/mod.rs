@@ -0,0 +1,138 @@
+//! Kernel timekeeping: a global tick counter driven by a timer IRQ, and functions built on it.
+
+pub mod clock;
+pub mod pit;
+pub mod rtc;
+
+use core::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use x86_64::instructions::interrupts;
+
+use crate::interrupts::irq;
+
+/// The default tick frequency, in Hz.
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// IRQ raised by the PIT.
+const PIT_IRQ: u8 = 0;
+
+/// Number of ticks since [`init`].
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The PIT divisor, which determines the length of a tick. 0 until [`init`] is called.
+static DIVISOR: AtomicU32 = AtomicU32::new(0);
+
+/// Start the PIT ticking at (approximately) `frequency` Hz.
+///
+/// # Panics
+///
+/// * If `frequency` is 0.
+pub fn init(frequency: u32) {
+    set_frequency(frequency);
+    irq::register_irq(PIT_IRQ, tick);
+}
+
+/// Change the tick frequency to (approximately) `frequency` Hz. Time already elapsed isn't
+/// affected.
+///
+/// # Panics
+///
+/// * If `frequency` is 0.
+pub fn set_frequency(frequency: u32) {
+    let divisor = pit::divisor_for(frequency);
+    interrupts::without_interrupts(|| {
+        // Keep the uptime continuous, by rescaling the ticks so far to the new tick length
+        let old_divisor = DIVISOR.swap(divisor, Ordering::Relaxed);
+        if old_divisor != 0 {
+            let ticks = TICKS.load(Ordering::Relaxed);
+            TICKS.store(
+                (ticks as u128 * old_divisor as u128 / divisor as u128) as u64,
+                Ordering::Relaxed,
+            );
+        }
+        pit::set_divisor(divisor);
+    });
+}
+
+fn tick(_irq: u8) {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    clock::pit_tick(ticks);
+}
+
+/// Returns the number of ticks since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the actual tick frequency, in Hz, or 0 if the timer hasn't been started.
+pub fn frequency() -> u32 {
+    match DIVISOR.load(Ordering::Relaxed) {
+        0 => 0,
+        divisor => pit::BASE_FREQUENCY / divisor,
+    }
+}
+
+/// Converts a number of ticks to a [`Duration`].
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let divisor = DIVISOR.load(Ordering::Relaxed) as u128;
+    let nanos = ticks as u128 * divisor * NANOS_PER_SEC / pit::BASE_FREQUENCY as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Converts a [`Duration`] to a number of ticks, rounding up.
+///
+/// # Panics
+///
+/// * If the timer hasn't been started.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let divisor = DIVISOR.load(Ordering::Relaxed) as u128;
+    assert_ne!(divisor, 0, "Timer not started");
+    let ticks =
+        (duration.as_nanos() * pit::BASE_FREQUENCY as u128).div_ceil(divisor * NANOS_PER_SEC);
+    ticks as u64
+}
+
+/// Returns the time since the timer was started.
+#[allow(dead_code)]
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Halt until at least `duration` has passed.
+///
+/// # Panics
+///
+/// * If the timer hasn't been started, or interrupts are disabled (so it will never tick).
+#[allow(dead_code)]
+pub fn sleep(duration: Duration) {
+    assert!(
+        interrupts::are_enabled(),
+        "Sleeping with interrupts disabled"
+    );
+    let end = ticks() + duration_to_ticks(duration);
+    while ticks() < end {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Spin until at least `duration` has passed, without halting the CPU, E.G. for short delays
+/// while polling hardware.
+///
+/// # Panics
+///
+/// * If the timer hasn't been started, or interrupts are disabled (so it will never tick).
+#[allow(dead_code)]
+pub fn busy_wait(duration: Duration) {
+    assert!(
+        interrupts::are_enabled(),
+        "Busy waiting with interrupts disabled"
+    );
+    let end = ticks() + duration_to_ticks(duration);
+    while ticks() < end {
+        core::hint::spin_loop();
+    }
+}
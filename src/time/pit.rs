@@ -0,0 +1,43 @@
+//! Driver for channel 0 of the 8253/8254 programmable interval timer, which raises IRQ 0 at a
+//! fixed rate.
+
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator driving the PIT, in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+/// Select channel 0, write the low then high byte of the reload value, mode 2 (rate generator).
+const CMD_CHANNEL0_RATE_GENERATOR: u8 = 0x34;
+
+/// Returns the reload value making the PIT fire as close to `frequency` Hz as it can.
+///
+/// # Panics
+///
+/// * If `frequency` is 0.
+pub fn divisor_for(frequency: u32) -> u32 {
+    assert_ne!(frequency, 0, "PIT frequency must be non-zero");
+    (BASE_FREQUENCY / frequency).clamp(1, 0x10000)
+}
+
+/// Make channel 0 fire every `divisor` oscillations, I.E. at `BASE_FREQUENCY / divisor` Hz.
+///
+/// # Panics
+///
+/// * If `divisor` isn't in `1..=0x10000`.
+pub fn set_divisor(divisor: u32) {
+    assert!(
+        (1..=0x10000).contains(&divisor),
+        "Invalid PIT divisor {}",
+        divisor
+    );
+    // A reload value of 0 means 0x10000
+    let reload = (divisor & 0xffff) as u16;
+    unsafe {
+        Port::<u8>::new(COMMAND).write(CMD_CHANNEL0_RATE_GENERATOR);
+        let mut data = Port::<u8>::new(CHANNEL0_DATA);
+        data.write(reload as u8);
+        data.write((reload >> 8) as u8);
+    }
+}
@@ -0,0 +1,356 @@
+//! Driver for the CMOS real-time clock, providing the wall-clock time.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use log::info;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::interrupts::irq;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+/// Bit of status register A set while the RTC is updating its registers.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Bit of status register B enabling the periodic interrupt.
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Bit of status register B set if the time is in binary, rather than BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Bit of status register B set if the hours are in 24 hour format.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Bit of the hours register set for PM times, in 12 hour format.
+const HOURS_PM: u8 = 1 << 7;
+
+/// The RTC only stores a 2 digit year, which is assumed to be in this century unless the FADT
+/// names a CMOS register holding the century.
+const DEFAULT_CENTURY: u16 = 20;
+
+/// IRQ raised by the RTC.
+const RTC_IRQ: u8 = 8;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Serialises access to the CMOS, since selecting a register and accessing it isn't atomic.
+static CMOS: Mutex<()> = Mutex::new(());
+
+/// Index of the CMOS register holding the century, from the FADT, or 0 if there isn't one.
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+/// UNIX timestamp at which the monotonic clock started.
+static BOOT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+/// Number of periodic interrupts received.
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Read CMOS register `register`. The CMOS must be locked.
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        Port::new(CMOS_ADDRESS).write(register);
+        Port::new(CMOS_DATA).read()
+    }
+}
+
+/// Write CMOS register `register`. The CMOS must be locked.
+fn write_register(register: u8, value: u8) {
+    unsafe {
+        Port::new(CMOS_ADDRESS).write(register);
+        Port::new(CMOS_DATA).write(value);
+    }
+}
+
+/// A broken-down UTC date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12.
+    pub month: u8,
+    /// 1-31.
+    pub day: u8,
+    /// 0-23.
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Read the current date and time from the RTC.
+    fn read_rtc() -> Self {
+        // The periodic interrupt handler also locks the CMOS
+        interrupts::without_interrupts(Self::read_rtc_locked)
+    }
+
+    fn read_rtc_locked() -> Self {
+        let _cmos = CMOS.lock();
+        let century_register = CENTURY_REGISTER.load(Ordering::Relaxed);
+
+        // Read until the same values are read twice, so we don't get half of an update
+        let read = || {
+            while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+                core::hint::spin_loop();
+            }
+            [
+                read_register(REG_SECONDS),
+                read_register(REG_MINUTES),
+                read_register(REG_HOURS),
+                read_register(REG_DAY),
+                read_register(REG_MONTH),
+                read_register(REG_YEAR),
+                if century_register != 0 {
+                    read_register(century_register)
+                } else {
+                    0
+                },
+            ]
+        };
+        let mut raw = read();
+        loop {
+            let again = read();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        let [second, minute, hour, day, month, year, century] = raw;
+
+        let status_b = read_register(REG_STATUS_B);
+        let decode = |value: u8| {
+            if status_b & STATUS_B_BINARY != 0 {
+                value
+            } else {
+                (value >> 4) * 10 + (value & 0xf)
+            }
+        };
+        let hour = if status_b & STATUS_B_24_HOUR != 0 {
+            decode(hour)
+        } else {
+            // 12 AM is midnight, and 12 PM is midday
+            decode(hour & !HOURS_PM) % 12 + if hour & HOURS_PM != 0 { 12 } else { 0 }
+        };
+
+        Self {
+            year: full_year(
+                (century_register != 0).then(|| decode(century)),
+                decode(year),
+            ),
+            month: decode(month),
+            day: decode(day),
+            hour,
+            minute: decode(minute),
+            second: decode(second),
+        }
+    }
+
+    /// Convert a UNIX timestamp to a date and time.
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let days = (timestamp / SECONDS_PER_DAY) as i64;
+        let seconds = timestamp % SECONDS_PER_DAY;
+
+        // Howard Hinnant's civil_from_days, with eras of 400 years starting on 0000-03-01
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Convert this date and time to a UNIX timestamp.
+    pub fn timestamp(&self) -> u64 {
+        // Howard Hinnant's days_from_civil
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month = self.month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days as u64 * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The current wall-clock time.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    /// Seconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub date: DateTime,
+}
+
+/// Read the date and time from the RTC. The wall-clock time is kept from then on by the
+/// monotonic clock.
+pub fn init() {
+    let timestamp = DateTime::read_rtc().timestamp();
+    let uptime = super::clock::monotonic_nanos() / 1_000_000_000;
+    BOOT_TIMESTAMP.store(timestamp - uptime, Ordering::Relaxed);
+}
+
+/// Use the century register named by the FADT, if there is one, and read the date and time from
+/// the RTC again with it. Must be called after [`crate::acpi::init`].
+pub fn init_century() {
+    if let Some(register) = crate::acpi::Fadt::get().and_then(|fadt| fadt.century_register()) {
+        info!("RTC century in CMOS register {:#x}", register);
+        CENTURY_REGISTER.store(register, Ordering::Relaxed);
+        init();
+    }
+}
+
+/// Returns the current wall-clock time.
+pub fn wall_clock() -> WallClock {
+    let timestamp =
+        BOOT_TIMESTAMP.load(Ordering::Relaxed) + super::clock::monotonic_nanos() / 1_000_000_000;
+    WallClock {
+        timestamp,
+        date: DateTime::from_timestamp(timestamp),
+    }
+}
+
+/// Enable the RTC's periodic interrupt on IRQ 8, at `32768 >> (rate - 1)` Hz.
+///
+/// # Panics
+///
+/// * If `rate` isn't in `3..=15` (8192 Hz to 2 Hz).
+#[allow(dead_code)]
+pub fn enable_periodic_interrupt(rate: u8) {
+    assert!((3..=15).contains(&rate), "Invalid RTC rate {}", rate);
+    interrupts::without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & 0xf0) | rate);
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // Acknowledge anything already pending, or the RTC won't raise another interrupt
+        read_register(REG_STATUS_C);
+    });
+    irq::register_irq(RTC_IRQ, periodic_interrupt);
+}
+
+/// Returns the number of periodic interrupts received since they were enabled.
+#[allow(dead_code)]
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+fn periodic_interrupt(_irq: u8) {
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    let _cmos = CMOS.lock();
+    read_register(REG_STATUS_C);
+}
+
+/// The full year from the 2 digit `year` and the `century` register, if there is one.
+fn full_year(century: Option<u8>, year: u8) -> u16 {
+    century.map_or(DEFAULT_CENTURY, u16::from) * 100 + u16::from(year)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Dates and times, with their UNIX timestamps.
+    const KNOWN: [(DateTime, u64); 8] = [
+        (date(1970, 1, 1, 0, 0, 0), 0),
+        (date(1972, 12, 31, 23, 59, 59), 94_694_399),
+        (date(2000, 2, 29, 0, 0, 0), 951_782_400),
+        (date(2000, 3, 1, 0, 0, 0), 951_868_800),
+        (date(2024, 2, 29, 0, 0, 0), 1_709_164_800),
+        (date(2038, 1, 19, 3, 14, 8), 2_147_483_648),
+        (date(2100, 2, 28, 23, 59, 59), 4_107_542_399),
+        (date(2100, 3, 1, 0, 0, 0), 4_107_542_400),
+    ];
+
+    #[test_case]
+    fn known_timestamps() {
+        for &(date, timestamp) in KNOWN.iter() {
+            assert_eq!(DateTime::from_timestamp(timestamp), date);
+            assert_eq!(date.timestamp(), timestamp);
+        }
+    }
+
+    #[test_case]
+    fn leap_years() {
+        // The day after the 28th of February, in leap years and not. 2100 is divisible by 4, but
+        // isn't a leap year as it's also divisible by 100 (and not by 400)
+        let years = [
+            (1972, true),
+            (1999, false),
+            (2000, true),
+            (2024, true),
+            (2100, false),
+        ];
+        for &(year, leap) in years.iter() {
+            let next_day =
+                DateTime::from_timestamp(date(year, 2, 28, 12, 0, 0).timestamp() + SECONDS_PER_DAY);
+            let expected = if leap { (2, 29) } else { (3, 1) };
+            assert_eq!((next_day.month, next_day.day), expected, "{}", year);
+        }
+    }
+
+    #[test_case]
+    fn century() {
+        assert_eq!(full_year(None, 24), 2024);
+        assert_eq!(full_year(Some(19), 99), 1999);
+        assert_eq!(full_year(Some(21), 0), 2100);
+    }
+
+    #[test_case]
+    fn round_trip() {
+        // Step by an amount that isn't a whole number of days, until 2200
+        let mut timestamp = 0;
+        while timestamp < 7_258_118_400 {
+            let date = DateTime::from_timestamp(timestamp);
+            assert!((1..=12).contains(&date.month) && (1..=31).contains(&date.day));
+            assert_eq!(date.timestamp(), timestamp, "{}", date);
+            timestamp += 3 * SECONDS_PER_DAY + 4321;
+        }
+    }
+}
@@ -0,0 +1,31 @@
+#!/bin/sh
+# Run a test kernel built by `make test` in Qemu, exiting with 0 if every test passed. The kernel
+# reports its result by writing to Qemu's isa-debug-exit device, which makes Qemu exit with
+# (code << 1) | 1, so 0x10 (success) becomes 33 and 0x11 (failure) becomes 35.
+
+set -e
+
+arch=${arch:-x86_64}
+kernel=$1
+isofiles=$kernel.isofiles
+iso=$kernel.iso
+
+mkdir -p "$isofiles/boot/grub"
+cp "$kernel" "$isofiles/boot/kernel.bin"
+cp "src/arch/$arch/grub.cfg" "$isofiles/boot/grub"
+grub-mkrescue -o "$iso" "$isofiles" 2> /dev/null
+rm -r "$isofiles"
+
+set +e
+timeout ${test_timeout:-60} qemu-system-x86_64 -boot d -cdrom "$iso" -serial stdio -display none \
+	-device isa-debug-exit,iobase=0xf4,iosize=0x04
+status=$?
+set -e
+
+case $status in
+	33) exit 0 ;;
+	35) echo "Tests failed" >&2 ;;
+	124) echo "Tests timed out" >&2 ;;
+	*) echo "Qemu exited with status $status" >&2 ;;
+esac
+exit 1